@@ -0,0 +1,601 @@
+use std::ops::Index;
+use clap::ValueEnum;
+
+use crate::{parser::{constant_condition, ExprType, OpType, StmtType}, tokenizer::{TokenType, UserType}};
+
+pub struct Generator <'a> {
+    stmts: &'a Vec<StmtType<'a>>,
+    index: usize,
+    // emit "\n" instead of the flushing std::endl
+    pub no_std_endl: bool,
+    // source text of every stmt for --annotate, empty when it continues the previous one
+    pub annotations: Vec<String>,
+    // source comments for --preserve-comments emitted before every stmt, the extra last entry goes after all of them
+    pub comments: Vec<String>,
+    // stmts generated so far, nested ones included, indexes `annotations` and `comments` like Parser::spans
+    visited: usize,
+    includes: String,
+    // any expect was emitted, main has to count failures
+    expects: bool,
+    // set by `freeze`, every later let is const
+    frozen: bool,
+    // main needs argc/argv
+    uses_args: bool,
+    // int arithmetic goes through CHECKED_HELPERS, which abort on overflow
+    pub checked_arithmetic: bool,
+    // any int arithmetic was emitted, the helpers are needed if it's checked
+    arithmetic: bool,
+    pub naming: Naming,
+}
+
+// int arithmetic for --checked-arithmetic, aborting instead of overflowing
+const CHECKED_HELPERS: &str = "[[noreturn]] inline void zynk_overflow() { std::cerr<<\"integer overflow\"<<std::endl; std::abort(); }
+inline int zynk_add(int a, int b) { int r; if (__builtin_add_overflow(a, b, &r)) { zynk_overflow(); } return r; }
+inline int zynk_sub(int a, int b) { int r; if (__builtin_sub_overflow(a, b, &r)) { zynk_overflow(); } return r; }
+inline int zynk_mul(int a, int b) { int r; if (__builtin_mul_overflow(a, b, &r)) { zynk_overflow(); } return r; }
+inline int zynk_div(int a, int b) { if (a == -2147483647-1 && b == -1) { zynk_overflow(); } return a / b; }
+inline int zynk_neg(int x) { return zynk_sub(0, x); }
+inline double zynk_neg(double x) { return -x; }
+";
+
+// every non-blank line of a source comment as a C++ line comment
+fn emit_comment(comment: &str) -> String {
+    let mut src = String::new();
+
+    for line in comment.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        src += "// ";
+        src += line;
+        src += "\n";
+    }
+
+    return src;
+}
+
+fn include(mut includes: String, include: &str) -> String {
+    if !includes.contains(&String::from(include)) {
+        includes += "#include ";
+        includes += include;
+        includes += "\n";
+    }
+
+    return includes;
+}
+
+/// Quotes `value` as a C++ string literal, escaping anything that would end or break it.
+pub fn emit_cpp_string(value: &str) -> String {
+    let mut literal = String::from("\"");
+
+    for char in value.chars() {
+        match char {
+            '"' => { literal += "\\\""; }
+            '\\' => { literal += "\\\\"; }
+            '\n' => { literal += "\\n"; }
+            '\t' => { literal += "\\t"; }
+            '\r' => { literal += "\\r"; }
+            char => { literal.push(char); }
+        }
+    }
+
+    literal += "\"";
+    return literal;
+}
+
+/// Every C++20 keyword and alternative operator token (`and`, `xor`, ...), none of them can name a variable.
+const CPP_KEYWORDS: &[&str] = &["alignas", "alignof", "and", "and_eq", "asm", "auto", "bitand", "bitor", "bool", "break", "case", "catch", "char", "char8_t", "char16_t", "char32_t", "class", "compl", "concept", "const", "consteval", "constexpr", "constinit", "const_cast", "continue", "co_await", "co_return", "co_yield", "decltype", "default", "delete", "do", "double", "dynamic_cast", "else", "enum", "explicit", "export", "extern", "false", "float", "for", "friend", "goto", "if", "inline", "int", "long", "mutable", "namespace", "new", "noexcept", "not", "not_eq", "nullptr", "operator", "or", "or_eq", "private", "protected", "public", "register", "reinterpret_cast", "requires", "return", "short", "signed", "sizeof", "static", "static_assert", "static_cast", "struct", "switch", "template", "this", "thread_local", "throw", "true", "try", "typedef", "typeid", "typename", "union", "unsigned", "using", "virtual", "void", "volatile", "wchar_t", "while", "xor", "xor_eq"];
+
+/// Names the generated main itself declares, a variable can't take them either.
+const GENERATED_NAMES: &[&str] = &["zynk_failures", "argc", "argv"];
+
+/// Turns a Zynk identifier into a valid C++ one, the same name always mangles the same way.
+/// Anything outside `[A-Za-z0-9_]` becomes `_u<hex>_`, so `é` is `_ue9_`, and a name
+/// clashing with a C++ keyword or one of `GENERATED_NAMES` gets a trailing `_`.
+pub fn mangle(name: &str) -> String {
+    let mut mangled = String::new();
+
+    for char in name.chars() {
+        if char.is_ascii_alphanumeric() || char == '_' {
+            mangled.push(char);
+        } else {
+            mangled += &format!("_u{:x}_", char as u32);
+        }
+    }
+
+    if CPP_KEYWORDS.contains(&mangled.as_str()) || GENERATED_NAMES.contains(&mangled.as_str()) {
+        mangled.push('_');
+    }
+
+    return mangled;
+}
+
+/// The C++ name a Zynk identifier is emitted as, `naming` first and then `mangle`.
+pub fn cpp_name(name: &str, naming: Naming) -> String {
+    return mangle(&naming.apply(name));
+}
+
+fn cpp_value(user_type: &UserType, naming: Naming) -> String {
+    match user_type {
+        // C++ has no negative literals, `-2147483648` would be a long
+        UserType::Int(x) if *x == i32::MIN as i64 => { return format!("({}-1)", x + 1); }
+        UserType::Int(x) => { return x.to_string(); }
+        UserType::Float(x) => { return format!("{:?}", x); }
+        UserType::String(x) => { return emit_cpp_string(x); }
+        UserType::Bool(x) => { return x.to_string(); }
+        UserType::Identifier(x) => { return cpp_name(x, naming); }
+    }
+}
+
+fn cpp_expr(expr: &ExprType, naming: Naming, checked: bool) -> String {
+    match expr {
+        ExprType::Literal(x) => { return cpp_value(x, naming); }
+        ExprType::Binary { op, lhs, rhs, cpp_type } if checked && *cpp_type == "int" && checked_helper(*op).is_some() => {
+            // as call arguments neither side needs parens
+            return format!("{}({}, {})", checked_helper(*op).unwrap(), cpp_expr(lhs, naming, checked), cpp_expr(rhs, naming, checked));
+        }
+        ExprType::Binary { op, lhs, rhs, .. } => {
+            let mut left = cpp_expr(lhs, naming, checked);
+            let mut right = cpp_expr(rhs, naming, checked);
+
+            // the tree already encodes precedence, parens keep C++ from regrouping it
+            if let ExprType::Binary { op: lhs_op, .. } = **lhs {
+                if lhs_op.precedence() < op.precedence() || clarify(*op, lhs_op) {
+                    left = format!("({})", left);
+                }
+            }
+
+            if let ExprType::Binary { op: rhs_op, .. } = **rhs {
+                if rhs_op.precedence() <= op.precedence() || clarify(*op, rhs_op) {
+                    right = format!("({})", right);
+                }
+            }
+
+            return format!("{} {} {}", left, op.cpp_symbol(), right);
+        }
+        ExprType::Address(x) => { return format!("&{}", cpp_name(x, naming)); }
+        // a missing argument is an empty string rather than reading past argv
+        ExprType::Argument(x) => { return format!("({} < argc ? std::string(argv[{}]) : std::string())", x, x); }
+        ExprType::ToString(x) => { return format!("std::to_string({})", cpp_expr(x, naming, checked)); }
+        ExprType::ToInt(x) => { return format!("std::stoi({})", cpp_expr(x, naming, checked)); }
+        ExprType::Not(x) => {
+            if let ExprType::Binary { .. } = **x {
+                return format!("!({})", cpp_expr(x, naming, checked));
+            }
+
+            return format!("!{}", cpp_expr(x, naming, checked));
+        }
+        // overloaded for int and double, the expression's type isn't known here
+        ExprType::Neg(x) if checked => { return format!("zynk_neg({})", cpp_expr(x, naming, checked)); }
+        ExprType::Neg(x) => {
+            // parens also keep `-(-x)` from turning into the `--` operator
+            if matches!(**x, ExprType::Binary { .. } | ExprType::Neg(_)) {
+                return format!("-({})", cpp_expr(x, naming, checked));
+            }
+
+            return format!("-{}", cpp_expr(x, naming, checked));
+        }
+    }
+}
+
+/// The `CHECKED_HELPERS` function standing in for `op` on ints with --checked-arithmetic.
+fn checked_helper(op: OpType) -> Option<&'static str> {
+    match op {
+        OpType::Add => { return Some("zynk_add"); }
+        OpType::Sub => { return Some("zynk_sub"); }
+        OpType::Mul => { return Some("zynk_mul"); }
+        OpType::Div => { return Some("zynk_div"); }
+        _ => { return None; }
+    }
+}
+
+/// Parens C++ wouldn't need but that make mixed logic readable (and keep -Wparentheses quiet):
+/// comparisons under `&&`/`||`, and `&&` under `||`.
+fn clarify(parent: OpType, child: OpType) -> bool {
+    return parent.is_logical() && (child.is_comparison() || (child.is_logical() && child != parent));
+}
+
+// whether the emitted code calls into <string>
+fn uses_string(expr: &ExprType) -> bool {
+    match expr {
+        ExprType::ToString(_) | ExprType::ToInt(_) | ExprType::Argument(_) => { return true; }
+        ExprType::Binary { lhs, rhs, .. } => { return uses_string(lhs) || uses_string(rhs); }
+        ExprType::Not(x) | ExprType::Neg(x) => { return uses_string(x); }
+        _ => { return false; }
+    }
+}
+
+// whether the emitted code calls a `CHECKED_HELPERS` function once overflow checks are on
+fn uses_arithmetic(expr: &ExprType) -> bool {
+    match expr {
+        ExprType::Binary { op, cpp_type, .. } if *cpp_type == "int" && checked_helper(*op).is_some() => { return true; }
+        ExprType::Neg(_) => { return true; }
+        ExprType::Binary { lhs, rhs, .. } => { return uses_arithmetic(lhs) || uses_arithmetic(rhs); }
+        ExprType::ToString(x) | ExprType::ToInt(x) | ExprType::Not(x) => { return uses_arithmetic(x); }
+        _ => { return false; }
+    }
+}
+
+fn uses_argv(expr: &ExprType) -> bool {
+    match expr {
+        ExprType::Argument(_) => { return true; }
+        ExprType::ToString(x) | ExprType::ToInt(x) | ExprType::Not(x) | ExprType::Neg(x) => { return uses_argv(x); }
+        ExprType::Binary { lhs, rhs, .. } => { return uses_argv(lhs) || uses_argv(rhs); }
+        _ => { return false; }
+    }
+}
+
+impl <'a> Generator <'a> {
+    pub fn new(stmts: &'a Vec<StmtType<'a>>) -> Generator<'a> {
+        return Generator {
+            stmts,
+            index: 0,
+            no_std_endl: false,
+            annotations: Vec::new(),
+            comments: Vec::new(),
+            visited: 0,
+            includes: String::new(),
+            expects: false,
+            frozen: false,
+            uses_args: false,
+            checked_arithmetic: false,
+            arithmetic: false,
+            naming: Naming::Preserve,
+        }
+    }
+
+    pub fn generate(&mut self) -> String {
+        let mut src = String::new();
+        let stmts = self.stmts;
+
+        while self.peek(None).is_some() {
+            let stmt = stmts.index(self.index);
+            src += self.generate_stmt(stmt).as_str();
+            self.accept();
+        }
+
+        if let Some(comment) = self.comments.get(self.visited) {
+            src += emit_comment(comment).as_str();
+        }
+
+        if self.expects {
+            // every expect runs, the exit code reports whether any failed
+            src = format!("int zynk_failures=0;\n{}return zynk_failures>0;\n", src);
+        }
+
+        let signature = if self.uses_args { "int main(int argc, char** argv)" } else { "int main()" };
+
+        if self.checked_arithmetic && self.arithmetic {
+            self.include("<iostream>");
+            self.include("<cstdlib>");
+            return format!("{}\n{}\n{} {{\n{}}}", self.includes, CHECKED_HELPERS, signature, src);
+        }
+
+        return format!("{}\n{} {{\n{}}}", self.includes, signature, src);
+    }
+
+    /// Steps `visited` over stmts that are never emitted, their comments are kept.
+    fn skip_stmts(self: &mut Self, stmts: &[StmtType]) -> String {
+        let mut src = String::new();
+
+        for stmt in stmts.iter() {
+            if let Some(comment) = self.comments.get(self.visited) {
+                src += emit_comment(comment).as_str();
+            }
+
+            self.visited += 1;
+
+            match stmt {
+                StmtType::If { then_body, else_body, .. } => {
+                    src += self.skip_stmts(then_body).as_str();
+                    src += self.skip_stmts(else_body).as_str();
+                }
+                StmtType::While { body, .. } => {
+                    src += self.skip_stmts(body).as_str();
+                }
+                _ => {}
+            }
+        }
+
+        return src;
+    }
+
+    fn generate_stmt(self: &mut Self, stmt: &StmtType) -> String {
+        let mut src = String::new();
+
+        if let Some(comment) = self.comments.get(self.visited) {
+            src += emit_comment(comment).as_str();
+        }
+
+        if let Some(annotation) = self.annotations.get(self.visited) {
+            // a block only gets its header, its body is annotated as it's emitted
+            let annotation = if matches!(stmt, StmtType::If { .. } | StmtType::While { .. }) { annotation.lines().next().unwrap_or("") } else { annotation.as_str() };
+
+            for line in annotation.lines() {
+                src += "// ";
+                src += line.trim();
+                src += "\n";
+            }
+        }
+
+        self.visited += 1;
+
+        match stmt {
+            StmtType::Print(exprs) => {
+                src += self.generate_print(exprs, "", true).as_str();
+            }
+            StmtType::Say(exprs) => {
+                src += self.generate_print(exprs, "\" \"<<", true).as_str();
+            }
+            StmtType::Write(exprs) => {
+                src += self.generate_print(exprs, "", false).as_str();
+            }
+            StmtType::RawPrint(expr) => {
+                self.include("<iostream>");
+                // `<<` binds tighter than most of what could be in there
+                src += "std::cout<<(";
+                src += expr;
+                src += ")<<";
+                src += self.endl();
+                src += ";\n";
+            }
+            StmtType::Debug(name) => {
+                self.include("<iostream>");
+                src += "std::cout<<";
+                src += emit_cpp_string(&format!("{} = ", name)).as_str();
+                src += "<<";
+                src += cpp_name(name, self.naming).as_str();
+                src += "<<";
+                src += self.endl();
+                src += ";\n";
+            }
+            StmtType::Expect { condition } => {
+                self.include("<iostream>");
+                self.expects = true;
+
+                if uses_string(condition) {
+                    self.include("<string>");
+                }
+
+                self.uses_args = self.uses_args || uses_argv(condition);
+                self.arithmetic = self.arithmetic || uses_arithmetic(condition);
+                let condition = cpp_expr(condition, self.naming, self.checked_arithmetic);
+                src += "if (";
+                src += condition.as_str();
+                src += ") { std::cerr<<";
+                src += emit_cpp_string(&format!("PASS: {}", condition)).as_str();
+                src += "<<";
+                src += self.endl();
+                src += "; } else { std::cerr<<";
+                src += emit_cpp_string(&format!("FAIL: {}", condition)).as_str();
+                src += "<<";
+                src += self.endl();
+                src += "; zynk_failures++; }\n";
+            }
+            StmtType::Let { key, value, cpp_type } => {
+                if let TokenType::UserType(UserType::Identifier(key_string)) = key {
+                    if self.frozen {
+                        src += "const ";
+                    }
+
+                    if uses_string(value) || *cpp_type == "std::string" {
+                        self.include("<string>");
+                    }
+
+                    self.uses_args = self.uses_args || uses_argv(value);
+                    self.arithmetic = self.arithmetic || uses_arithmetic(value);
+                    src += cpp_type;
+                    src += " ";
+                    src += cpp_name(key_string, self.naming).as_str();
+                    src += "=";
+                    src += cpp_expr(value, self.naming, self.checked_arithmetic).as_str();
+                    src += ";\n";
+                }
+            }
+            StmtType::Assign { key, value } => {
+                if uses_string(value) {
+                    self.include("<string>");
+                }
+
+                self.uses_args = self.uses_args || uses_argv(value);
+                self.arithmetic = self.arithmetic || uses_arithmetic(value);
+                src += cpp_name(key, self.naming).as_str();
+                src += "=";
+                src += cpp_expr(value, self.naming, self.checked_arithmetic).as_str();
+                src += ";\n";
+            }
+            StmtType::Freeze => {
+                self.frozen = true;
+            }
+            StmtType::Here(line) => {
+                self.include("<iostream>");
+                src += "std::cout<<\"[line ";
+                src += line.to_string().as_str();
+                src += "]\"<<";
+                src += self.endl();
+                src += ";\n";
+            }
+            StmtType::If { condition, then_body, else_body } if constant_condition(condition).is_some() => {
+                // only the branch that runs is emitted, still in braces so its lets stay scoped
+                let (taken, skipped) = if constant_condition(condition) == Some(true) { (then_body, else_body) } else { (else_body, then_body) };
+
+                if constant_condition(condition) == Some(false) {
+                    src += self.skip_stmts(skipped).as_str();
+                }
+
+                if !taken.is_empty() {
+                    src += "{\n";
+
+                    for body_stmt in taken.iter() {
+                        src += self.generate_stmt(body_stmt).as_str();
+                    }
+
+                    src += "}\n";
+                }
+
+                if constant_condition(condition) == Some(true) {
+                    src += self.skip_stmts(skipped).as_str();
+                }
+            }
+            StmtType::If { condition, then_body, else_body } => {
+                if uses_string(condition) {
+                    self.include("<string>");
+                }
+
+                self.uses_args = self.uses_args || uses_argv(condition);
+                self.arithmetic = self.arithmetic || uses_arithmetic(condition);
+                src += "if (";
+                src += cpp_expr(condition, self.naming, self.checked_arithmetic).as_str();
+                src += ") {\n";
+
+                for body_stmt in then_body.iter() {
+                    src += self.generate_stmt(body_stmt).as_str();
+                }
+
+                if !else_body.is_empty() {
+                    src += "} else {\n";
+
+                    for body_stmt in else_body.iter() {
+                        src += self.generate_stmt(body_stmt).as_str();
+                    }
+                }
+
+                src += "}\n";
+            }
+            StmtType::While { condition, body } => {
+                if uses_string(condition) {
+                    self.include("<string>");
+                }
+
+                self.uses_args = self.uses_args || uses_argv(condition);
+                self.arithmetic = self.arithmetic || uses_arithmetic(condition);
+                src += "while (";
+                src += cpp_expr(condition, self.naming, self.checked_arithmetic).as_str();
+                src += ") {\n";
+
+                for body_stmt in body.iter() {
+                    src += self.generate_stmt(body_stmt).as_str();
+                }
+
+                src += "}\n";
+            }
+        }
+
+        return src;
+    }
+
+    /// A cout chain of `exprs` with `separator` between (never after) them.
+    fn generate_print(self: &mut Self, exprs: &[ExprType], separator: &str, newline: bool) -> String {
+        let mut src = String::new();
+
+        self.include("<iostream>");
+        src += "std::cout<<";
+
+        for (i, expr) in exprs.iter().enumerate() {
+            if uses_string(expr) {
+                self.include("<string>");
+            }
+
+            if i > 0 {
+                src += separator;
+            }
+
+            self.uses_args = self.uses_args || uses_argv(expr);
+            self.arithmetic = self.arithmetic || uses_arithmetic(expr);
+
+            // `<<` binds tighter than any comparison or logical operator
+            if matches!(expr, ExprType::Binary { op, .. } if op.is_comparison() || op.is_logical()) {
+                src += format!("({})", cpp_expr(expr, self.naming, self.checked_arithmetic)).as_str();
+            } else {
+                src += cpp_expr(expr, self.naming, self.checked_arithmetic).as_str();
+            }
+
+            if newline || i + 1 < exprs.len() {
+                src += "<<";
+            }
+        }
+
+        if newline {
+            src += self.endl();
+        }
+
+        src += ";\n";
+
+        return src;
+    }
+
+    fn include(self: &mut Self, header: &str) {
+        self.includes = include(std::mem::take(&mut self.includes), header);
+    }
+
+    fn endl(self: &Self) -> &'static str {
+        if self.no_std_endl {
+            return "\"\\n\"";
+        } else {
+            return "std::endl";
+        }
+    }
+
+    fn peek(self: &Self, _offset: Option<usize>) -> Option<&StmtType<'_>> {
+        let offset = _offset.unwrap_or(0);
+
+        if self.index + offset < self.stmts.len() {
+            let stmt = self.stmts.index(self.index + offset);
+            return Some(stmt);
+        } else {
+            return None;
+        }
+    }
+
+    fn accept(self: &mut Self) -> &StmtType<'_> {
+        self.index += 1;
+        return self.stmts.index(self.index - 1);
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Naming {
+    /// `myVar` becomes `my_var`
+    Snake,
+    /// `my_var` becomes `myVar`
+    Camel,
+    /// Names are emitted as written
+    Preserve,
+}
+
+impl Naming {
+    pub fn apply(self: &Self, name: &str) -> String {
+        let mut out = String::new();
+
+        match self {
+            Naming::Snake => {
+                for char in name.chars() {
+                    if char.is_uppercase() {
+                        if !out.is_empty() && !out.ends_with('_') {
+                            out.push('_');
+                        }
+
+                        out.extend(char.to_lowercase());
+                    } else {
+                        out.push(char);
+                    }
+                }
+            }
+            Naming::Camel => {
+                let mut upper = false;
+
+                for char in name.chars() {
+                    // leading underscores are kept, the rest mark a word boundary
+                    if char == '_' && !out.trim_start_matches('_').is_empty() {
+                        upper = true;
+                    } else if upper {
+                        out.extend(char.to_uppercase());
+                        upper = false;
+                    } else {
+                        out.push(char);
+                    }
+                }
+            }
+            Naming::Preserve => { out += name; }
+        }
+
+        return out;
+    }
+}
@@ -0,0 +1,204 @@
+#![allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
+
+pub mod generator;
+pub mod parser;
+pub mod tokenizer;
+
+use std::collections::HashMap;
+
+use generator::{Generator, Naming};
+use parser::{tree, Parser, ParserError};
+use tokenizer::{Token, Tokenizer};
+
+/// A tokenizer or parser error and the 1-based position it was found at.
+#[derive(Debug, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+    // chars to underline, the whole token the error is about
+    pub width: usize,
+    // "tokenizing" or "parsing"
+    pub stage: &'static str,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "line {}, column {}: {}", self.line, self.col, self.message);
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// How `compile` tokenizes, parses and generates, the defaults are what plain `compile` always did.
+#[derive(Clone)]
+pub struct CompileOptions {
+    // `alias = keyword` entries from a keyword map
+    pub keywords: HashMap<String, String>,
+    // report every tokenizer error instead of stopping at the first
+    pub keep_going_tokens: bool,
+    pub trace: bool,
+    pub naming: Naming,
+    pub strict: bool,
+    pub max_depth: usize,
+    pub compat: bool,
+    pub no_std_endl: bool,
+    pub checked_arithmetic: bool,
+    // precede every generated statement with its source
+    pub annotate: bool,
+    pub preserve_comments: bool,
+    // fill `Compilation::tree` with the parsed statements
+    pub tree: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> CompileOptions {
+        return CompileOptions {
+            keywords: HashMap::new(),
+            keep_going_tokens: true,
+            trace: false,
+            naming: Naming::Preserve,
+            strict: false,
+            max_depth: 128,
+            compat: false,
+            no_std_endl: false,
+            checked_arithmetic: false,
+            annotate: false,
+            preserve_comments: false,
+            tree: false,
+        }
+    }
+}
+
+/// The C++ or every error found, and what the stages found on the way there.
+pub struct Compilation {
+    pub result: Result<String, Vec<CompileError>>,
+    pub warnings: Vec<String>,
+    pub tokens: Vec<Token>,
+    pub stmts: usize,
+    // the parsed statements as an indented tree, with `CompileOptions::tree`
+    pub tree: Option<String>,
+}
+
+/// Tokenizes `content` with the keywords and error handling `options` ask for. It failed if it
+/// recorded any errors.
+pub fn tokenize<'a>(content: &'a String, options: &CompileOptions) -> Tokenizer<'a> {
+    let mut tokenizer = Tokenizer::new(content);
+    tokenizer.keep_going = options.keep_going_tokens;
+    tokenizer.keywords = options.keywords.clone();
+    tokenizer.run();
+
+    return tokenizer;
+}
+
+/// The errors `tokenize` recorded, the tokenizer already knows their positions.
+pub fn tokenizer_errors(tokenizer: &Tokenizer) -> Vec<CompileError> {
+    return tokenizer.errors.iter().map(|(line, col, message)| CompileError { message: message.clone(), line: *line, col: *col, width: 1, stage: "tokenizing" }).collect();
+}
+
+/// Runs the whole pipeline over `source`, returning the C++ or every error found.
+pub fn compile(source: &str, options: &CompileOptions) -> Compilation {
+    let content = String::from(source);
+    let mut tokenizer = tokenize(&content, options);
+    let mut warnings = std::mem::take(&mut tokenizer.warnings);
+
+    if !tokenizer.errors.is_empty() {
+        return Compilation { result: Err(tokenizer_errors(&tokenizer)), warnings, tokens: tokenizer.tokens, stmts: 0, tree: None };
+    }
+
+    // the parser borrows the tokens, everything it's needed for is done before they're moved out
+    let (result, stmts, tree) = {
+        let mut parser = Parser::new(&tokenizer.tokens);
+        parser.trace = options.trace;
+        parser.naming = options.naming;
+        parser.strict = options.strict;
+        parser.max_depth = options.max_depth;
+        parser.compat = options.compat;
+        let parsed = parser.run();
+        warnings.append(&mut parser.warnings);
+
+        let tree = if options.tree { Some(tree(&parser.stmts, 1)) } else { None };
+
+        let result = match parsed {
+            ParserError::Ok => {
+                let mut generator = Generator::new(&parser.stmts);
+                generator.no_std_endl = options.no_std_endl;
+                generator.checked_arithmetic = options.checked_arithmetic;
+                generator.naming = options.naming;
+
+                if options.annotate {
+                    generator.annotations = annotate(&content, &tokenizer.spans, &parser.spans);
+                }
+
+                if options.preserve_comments {
+                    generator.comments = attach_comments(&tokenizer.comments, &tokenizer.tokens, &tokenizer.spans, &parser.spans);
+                }
+
+                Ok(generator.generate())
+            }
+            ParserError::Err(_) => {
+                Err(parser.errors.iter().map(|(index, message)| {
+                    let (line, col) = parser.position(*index);
+                    // the caret covers the whole token, or a single column past the end of the file
+                    let width = match tokenizer.spans.get(*index) {
+                        Some((start, end)) => end - start,
+                        None => 1,
+                    };
+                    let col = if *index < tokenizer.tokens.len() { col } else { col + tokenizer.spans.last().map_or(0, |(start, end)| end - start) };
+
+                    return CompileError { message: message.clone(), line, col, width, stage: "parsing" };
+                }).collect())
+            }
+        };
+
+        (result, parser.stmts.len(), tree)
+    };
+
+    return Compilation { result, warnings, tokens: tokenizer.tokens, stmts, tree };
+}
+
+/// Maps every stmt's token range back to the source text it was parsed from.
+fn annotate(content: &str, token_spans: &[(usize, usize)], stmt_spans: &[(usize, usize)]) -> Vec<String> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut annotations = Vec::new();
+
+    for (index, (first, last)) in stmt_spans.iter().enumerate() {
+        if index > 0 && stmt_spans[index - 1] == (*first, *last) {
+            annotations.push(String::new());
+        } else {
+            let start = token_spans[*first].0;
+            let end = token_spans[*last - 1].1;
+            annotations.push(chars[start..end].iter().collect());
+        }
+    }
+
+    return annotations;
+}
+
+/// Groups comments by the stmt they're emitted before: the next one to start, unless a stmt
+/// starts earlier on the comment's own line, so a trailing `// ...` stays with its stmt. Spans are
+/// in generation order, which keeps comments inside a block next to the nested stmts. The extra
+/// last entry holds comments after the last stmt.
+fn attach_comments(comments: &[(usize, usize, String)], tokens: &[Token], token_spans: &[(usize, usize)], stmt_spans: &[(usize, usize)]) -> Vec<String> {
+    let mut attached = vec![String::new(); stmt_spans.len() + 1];
+    // char index and line every stmt starts at, never decreasing as a block comes before its body
+    let starts: Vec<(usize, usize)> = stmt_spans.iter().map(|(first, _)| (token_spans[*first].0, tokens[*first].line)).collect();
+    let mut next = 0;
+
+    for (start, line, text) in comments.iter() {
+        while next < starts.len() && starts[next].0 < *start {
+            next += 1;
+        }
+
+        let mut stmt = next;
+
+        while stmt > 0 && starts[stmt - 1].1 == *line {
+            stmt -= 1;
+        }
+
+        attached[stmt] += text;
+        attached[stmt] += "\n";
+    }
+
+    return attached;
+}
@@ -1,374 +1,600 @@
-use std::{fs::read_to_string, ops::Index, path::PathBuf};
-use clap::Parser as ClapParser;
-
-#[derive(Debug, PartialEq, Clone)]
-#[allow(dead_code)]
-enum UserType {
-    String(String),
-    Int(i32),
-}
+#![allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
 
-#[derive(Debug, PartialEq)]
-enum TokenType {
-    UserType(UserType),
-    Print,
-    Let,
-    To,
-    OpenParen,
-    CloseParen,
-    Comma,
-}
+use std::{collections::{hash_map::DefaultHasher, HashMap}, error::Error, fs::read_to_string, hash::{Hash, Hasher}, io::Write, path::{Path, PathBuf}, process::{Command, Stdio}, time::{Duration, Instant}};
+use clap::{Parser as ClapParser, ValueEnum};
 
-enum ParserError <'a> {
-    Ok,
-    Err(&'a str)
-}
+use language01::{compile, generator::Naming, tokenize, tokenizer::{parse_keyword_map, TokenType, Tokenizer, TokenizerError}, tokenizer_errors, CompileError, CompileOptions};
 
-#[derive(Debug)]
-enum StmtType <'a> {
-    Print(Vec<UserType>),
-    Let {
-        key: &'a TokenType,
-        value: &'a TokenType,
-    },
+#[derive(Clone, ValueEnum)]
+enum Emit {
+    /// Source, tokens, AST and C++ one after another
+    All,
 }
 
-struct Tokenizer <'a> {
-    content: &'a String,
-    tokens: Vec<TokenType>,
-    index: usize,
+#[derive(ClapParser)]
+struct Cli {
+    #[arg(required_unless_present = "eval")]
+    file: Option<PathBuf>,
+
+    /// Compile and run this source instead of a file, a lone expression is printed
+    #[arg(long, conflicts_with_all = ["file", "output", "tree", "emit_tokens"])]
+    eval: Option<String>,
+
+    /// Print the parsed statements as an indented tree instead of compiling
+    #[arg(long, visible_alias = "emit-ast")]
+    tree: bool,
+
+    /// Log every parser decision and consumed token to stderr
+    #[arg(long)]
+    trace: bool,
+
+    /// File of `alias = keyword` lines adding alternative names for keywords
+    #[arg(long)]
+    keyword_map: Option<PathBuf>,
+
+    /// Print the token stream, one `line:col token` per line, and stop before parsing
+    #[arg(long)]
+    emit_tokens: bool,
+
+    /// How identifiers are renamed in the generated C++
+    #[arg(long, value_enum, default_value_t = Naming::Preserve)]
+    naming: Naming,
+
+    /// Dump the compilation stages instead of the usual banner
+    #[arg(long, value_enum)]
+    emit: Option<Emit>,
+
+    /// End printed lines with "\n" instead of the flushing std::endl
+    #[arg(long)]
+    no_std_endl: bool,
+
+    /// Abort with "integer overflow" instead of overflowing int arithmetic (needs g++ or clang++)
+    #[arg(long)]
+    checked_arithmetic: bool,
+
+    /// Precede every generated statement with a comment holding its source
+    #[arg(long)]
+    annotate: bool,
+
+    /// Carry `//` and `/* */` comments from the source into the generated C++
+    #[arg(long)]
+    preserve_comments: bool,
+
+    /// Prefix every generated C++ line with its line number, like `cat -n`
+    #[arg(long, conflicts_with = "run")]
+    line_numbers: bool,
+
+    /// Pipe the generated C++ through clang-format when it is installed
+    #[arg(long)]
+    clang_format: bool,
+
+    /// Make likely mistakes, like an empty block, errors instead of warnings
+    #[arg(long)]
+    strict: bool,
+
+    /// Fail the build when it gives more than this many warnings
+    #[arg(long)]
+    fail_on_warning_count: Option<usize>,
+
+    /// Print undeclared bare words like `print(hello)` as text, the way files written before variables expect
+    #[arg(long)]
+    compat: bool,
+
+    /// How deeply if/while blocks may nest before it's an error
+    #[arg(long, default_value_t = 128)]
+    max_depth: usize,
+
+    /// Report every tokenizer error instead of stopping at the first
+    #[arg(long)]
+    keep_going_tokens: bool,
+
+    /// Write build metadata (counts, success) as JSON to this path
+    #[arg(long)]
+    summary_json: Option<PathBuf>,
+
+    /// Write only the generated C++ to this file instead of printing the banner
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Compile the generated C++ with $ZYNK_CXX (default g++) and run it
+    #[arg(long, conflicts_with = "output")]
+    run: bool,
+
+    /// Kill the program started by --run once it has run this many seconds
+    #[arg(long, requires = "run", value_parser = clap::value_parser!(u64).range(1..))]
+    time_limit: Option<u64>,
+
+    /// File the program started by --run reads as its stdin
+    #[arg(long, requires = "run")]
+    input: Option<PathBuf>,
+
+    /// Print the source with its comments removed, or write it to -o, and stop before parsing
+    #[arg(long, conflicts_with_all = ["run", "eval"])]
+    strip_comments: bool,
+
+    /// Tokenize the input this many times and print the average time taken
+    #[arg(long, hide = true, value_parser = clap::value_parser!(u32).range(1..))]
+    bench_tokenize: Option<u32>,
 }
 
-impl Tokenizer <'_> {
-    fn new(content: &String) -> Tokenizer {
-        return Tokenizer {
-            content: content,
-            tokens: Vec::new(),
-            index: 0,
-        }
-    }
+/// C++ compiler used by --run, `ZYNK_CXX` overrides the default g++.
+fn cxx_command() -> String {
+    return std::env::var("ZYNK_CXX").unwrap_or(String::from("g++"));
+}
 
-    fn run(self: &mut Self) {
-        let buffer = &mut String::new();
-
-        while self.peek(None).is_some() {
-            if self.peek(None).unwrap().is_alphabetic() {
-                self.accept_to_buffer(buffer);
-
-                while self.peek(None).is_some() && self.peek(None).unwrap().is_alphanumeric() {
-                    self.accept_to_buffer(buffer);
-                }
-
-                if buffer == "print" {
-                    self.tokens.push(TokenType::Print);
-                } else if buffer == "let" {
-                    self.tokens.push(TokenType::Let);
-                } else if buffer == "to" {
-                    self.tokens.push(TokenType::To);
-                } else {
-                    self.tokens.push(TokenType::UserType(UserType::String(buffer.to_string())))
-                }
-
-                buffer.clear();
-            } else if self.peek(None).unwrap().is_numeric() {
-                self.accept_to_buffer(buffer);
-
-                while self.peek(None).is_some() && self.peek(None).unwrap().is_numeric() {
-                    self.accept_to_buffer(buffer);
-                }
-
-                let user_type = UserType::Int(buffer.parse().unwrap());
-                self.tokens.push(TokenType::UserType(user_type));
-                buffer.clear();
-            } else if self.peek(None).unwrap() == '(' {
-                self.accept();
-
-                self.tokens.push(TokenType::OpenParen);
-            } else if self.peek(None).unwrap() == ')' {
-                self.accept();
-
-                self.tokens.push(TokenType::CloseParen);
-            } else if self.peek(None).unwrap() == ',' {
-                self.accept();
-
-                self.tokens.push(TokenType::Comma);
-            } else {
-                self.accept();
+/// Compiles `src` into a temporary binary and runs it with inherited stdio, or `input` as stdin,
+/// returning its exit code, or an error once it has run for longer than `time_limit`.
+/// The binary is named after a hash of the source and compiler and kept, so running the same program
+/// again skips the C++ compile.
+fn run_cpp(src: &str, input: Option<&Path>, time_limit: Option<Duration>) -> Result<i32, String> {
+    let cxx = cxx_command();
+    let mut hasher = DefaultHasher::new();
+    (src, &cxx).hash(&mut hasher);
+    let hash = hasher.finish();
+    let binary = std::env::temp_dir().join(format!("zynk-{:016x}", hash)).with_extension(std::env::consts::EXE_EXTENSION);
+
+    if !binary.exists() {
+        // built under a per-process name and renamed, so a concurrent run never sees half a binary
+        let base = std::env::temp_dir().join(format!("zynk-{:016x}-{}", hash, std::process::id()));
+        let source = base.with_extension("cpp");
+        let partial = base.with_extension(std::env::consts::EXE_EXTENSION);
+
+        std::fs::write(&source, src).map_err(|err| format!("could not write '{}': {}", source.display(), err))?;
+
+        let compiled = Command::new(&cxx).arg(&source).arg("-o").arg(&partial).status();
+        let _ = std::fs::remove_file(&source);
+
+        match compiled {
+            Ok(status) if status.success() => {}
+            Ok(_) => { return Err(format!("{} failed to compile the generated C++", cxx)); }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(format!("C++ compiler '{}' not found, install it or point ZYNK_CXX at one", cxx));
             }
+            Err(err) => { return Err(format!("could not run '{}': {}", cxx, err)); }
         }
+
+        std::fs::rename(&partial, &binary).map_err(|err| format!("could not write '{}': {}", binary.display(), err))?;
     }
 
-    fn peek(self: &Self, _offset: Option<usize>) -> Option<char> {
-        let offset = if _offset.is_some() { _offset.unwrap() } else { 0 };
+    let stdin = match input {
+        Some(input) => Stdio::from(std::fs::File::open(input).map_err(|err| format!("could not read '{}': {}", input.display(), err))?),
+        None => Stdio::inherit(),
+    };
 
-        if self.index + offset < self.content.len() {
-            let str = &self.content[(self.index + offset)..(self.index + offset + 1)];
-            return str.chars().last();
-        } else {
-            return None;
+    let mut child = Command::new(&binary).stdin(stdin).spawn().map_err(|err| format!("could not run the compiled program: {}", err))?;
+    let started = Instant::now();
+
+    loop {
+        match child.try_wait() {
+            // a program killed by a signal has no exit code
+            Ok(Some(status)) => { return Ok(status.code().unwrap_or(1)); }
+            Ok(None) => {}
+            Err(err) => { return Err(format!("could not run the compiled program: {}", err)); }
         }
-    }
 
-    fn accept(self: &mut Self) -> Option<char> {
-        self.index += 1;
-        return self.content[self.index - 1 .. self.index].chars().last();
-    }
+        if time_limit.is_some_and(|limit| started.elapsed() >= limit) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("program timed out after {} seconds", time_limit.unwrap().as_secs()));
+        }
 
-    fn accept_to_buffer(self: &mut Self, buffer: &mut String) {
-        buffer.push(self.accept().unwrap());
+        std::thread::sleep(Duration::from_millis(10));
     }
 }
 
-struct Parser <'a> {
-    tokens: &'a Vec<TokenType>,
-    stmts: Vec<StmtType<'a>>,
-    index: usize,
-}
 
-impl Parser <'_> {
-    fn parse_stmt(self: &mut Self) -> ParserError {
-        if TokenType::Print == *self.peek(None).unwrap() {
-            self.accept();
-
-            if self.peek(None).is_some() && TokenType::OpenParen == *self.peek(None).unwrap() {
-                let mut user_types: Vec<UserType> = Vec::new();
-
-                self.accept();
-                
-                while self.peek(None).is_some() && matches!(self.peek(None).unwrap(), TokenType::UserType(_)) {
-                    let TokenType::UserType(x) = self.peek(None).unwrap() else {
-                        return ParserError::Err("Expected type Int inside print statement");
-                    };
-
-                    user_types.push(x.clone());
-
-                    self.accept();
-
-                    if self.peek(None).is_some() && TokenType::Comma == *self.peek(None).unwrap() {
-                        // found comma, gonna keep looking for values
-                        self.accept();
-                    } else {
-                        // didn't find comma, gonna stop now
-                        break;
-                    }
-                }
-
-                if self.peek(None).is_some() && TokenType::CloseParen == *self.peek(None).unwrap() {
-                    self.accept();
-
-                    // println!("{:?}", user_types);
-
-                    self.stmts.push(StmtType::Print(user_types));
-                } else {
-                    return ParserError::Err("Expected ')' to end print statement")
-                }
-            } else {
-                return ParserError::Err("Expected '(' to start print statement")
-            }
-        } else if TokenType::Let == *self.peek(None).unwrap() {
-            self.accept();
-
-            if self.peek(None).is_some() && matches!(self.peek(None).unwrap(), TokenType::UserType(UserType::String(_))) {
-                let key_token = self.tokens.index(self.index);
-                self.accept();
-
-                if self.peek(None).is_some() && TokenType::To == *self.peek(None).unwrap() {
-                    self.accept();
-
-                    if self.peek(None).is_some() && matches!(self.peek(None).unwrap(), TokenType::UserType(_)) {
-                        let value_token = self.tokens.index(self.index);
-                        self.accept();
-                        
-                        let stmt = StmtType::Let { key: key_token, value: value_token };
-                        self.stmts.push(stmt);
-                    } else {
-                        return ParserError::Err("Expected value after 'to'")
-                    }
-                } else {
-                    return ParserError::Err("Expected 'to' after variable name")
-                }
-            } else {
-                return ParserError::Err("Expected variable name after 'let'")
+/// `content` without the `comments` the tokenizer found. Lines that only held a comment are
+/// dropped, everything else is kept as written.
+fn strip_comments(content: &str, comments: &[(usize, usize, String)]) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let mut comments = comments.iter().peekable();
+    let mut out = String::new();
+    let mut line = String::new();
+    // the current line lost a comment, so its trailing whitespace goes too
+    let mut stripped = false;
+    let mut index = 0;
+
+    loop {
+        if comments.peek().is_some_and(|(start, _, _)| *start == index) {
+            let (start, _, text) = comments.next().unwrap();
+            let block = chars.get(start + 1) == Some(&'*');
+            let end = start + text.chars().count() + if block { 4 } else { 2 };
+
+            // `let/* */x` still needs something between the two tokens
+            if block && line.ends_with(|char: char| !char.is_whitespace()) && chars.get(end).is_some_and(|char| !char.is_whitespace()) {
+                line.push(' ');
             }
-        } else {
-            self.accept();
+
+            stripped = true;
+            index = end;
+            continue;
+        }
+
+        let char = chars.get(index).copied();
+        index += 1;
+
+        if let Some(char) = char.filter(|char| *char != '\n') {
+            line.push(char);
+            continue;
         }
 
-        return ParserError::Ok;
+        let ending = if line.ends_with('\r') { "\r\n" } else { "\n" };
+
+        if !stripped {
+            out += &line;
+            out += if char.is_some() { "\n" } else { "" };
+        } else if !line.trim().is_empty() {
+            out += line.trim_end();
+            out += if char.is_some() { ending } else { "" };
+        }
+
+        if char.is_none() {
+            return out;
+        }
+
+        line.clear();
+        stripped = false;
     }
+}
 
-    fn run(self: &mut Self) -> ParserError {
-        while self.peek(None).is_some() {
-            match self.parse_stmt() {
-                ParserError::Ok => { continue; }
-                ParserError::Err(err) => { println!("!!! -> Erorr while parsing: {}", err) }   
-            }
+fn clang_format_command() -> Command {
+    let mut command = Command::new("clang-format");
+    command.arg("--assume-filename=main.cpp").stdin(Stdio::piped()).stdout(Stdio::piped());
+
+    return command;
+}
+
+/// Runs `src` through clang-format, falling back to `src` itself when that isn't possible.
+fn clang_format(src: String) -> String {
+    let Ok(mut child) = clang_format_command().spawn() else {
+        eprintln!("warning: clang-format is not installed, printing unformatted output");
+        return src;
+    };
+
+    // stdin has to be dropped before waiting so clang-format sees EOF
+    let written = child.stdin.take().map(|mut stdin| stdin.write_all(src.as_bytes()));
+
+    match child.wait_with_output() {
+        Ok(output) if matches!(written, Some(Ok(()))) && output.status.success() => {
+            return String::from_utf8(output.stdout).unwrap_or(src);
+        }
+        _ => {
+            eprintln!("warning: clang-format failed, printing unformatted output");
+            return src;
         }
+    }
+}
+
+struct BuildSummary <'a> {
+    input: &'a Path,
+    // where the C++ was written with -o, null when it went to stdout
+    output: Option<&'a Path>,
+    tokens: usize,
+    stmts: usize,
+    warnings: usize,
+    success: bool,
+}
 
-        return ParserError::Ok;
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+
+    for char in value.chars() {
+        match char {
+            '"' => { escaped += "\\\""; }
+            '\\' => { escaped += "\\\\"; }
+            '\n' => { escaped += "\\n"; }
+            char if (char as u32) < 0x20 => { escaped += &format!("\\u{:04x}", char as u32); }
+            char => { escaped.push(char); }
+        }
     }
 
-    fn peek(self: &Self, _offset: Option<usize>) -> Option<&TokenType> {
-        let offset = if _offset.is_some() { _offset.unwrap() } else { 0 };
+    escaped += "\"";
+    return escaped;
+}
 
-        if self.index + offset < self.tokens.len() {
-            let token = self.tokens.index(self.index);
-            return Some(token);
-        } else {
-            return None;
+impl BuildSummary <'_> {
+    fn to_json(self: &Self) -> String {
+        let mut json = String::from("{\n");
+        json += "  \"target\": \"cpp\",\n";
+        json += &format!("  \"input\": {},\n", json_string(&self.input.display().to_string()));
+        match self.output {
+            Some(output) => { json += &format!("  \"output\": {},\n", json_string(&output.display().to_string())); }
+            None => { json += "  \"output\": null,\n"; }
         }
+        json += &format!("  \"tokens\": {},\n", self.tokens);
+        json += &format!("  \"statements\": {},\n", self.stmts);
+        json += &format!("  \"warnings\": {},\n", self.warnings);
+        json += &format!("  \"success\": {}\n", self.success);
+        json += "}\n";
+
+        return json;
     }
+}
 
-    fn accept(self: &mut Self) {
-        self.index += 1;
+fn write_summary(path: &Path, summary: &BuildSummary) {
+    if let Err(err) = std::fs::write(path, summary.to_json()) {
+        println!("!!! -> Error: could not write summary '{}': {}", path.display(), err);
     }
 }
 
-struct Generator <'a> {
-    stmts: &'a Vec<StmtType<'a>>,
-    index: usize,
+/// An error framed like rustc's, pointing `width` carets at `line`:`col` of `content`.
+fn render_diagnostic(path: &Path, content: &str, stage: &str, message: &str, line: usize, col: usize, width: usize) -> String {
+    let source_line = content.lines().nth(line - 1).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+    // a token can't be underlined past the end of its line (multi-line strings)
+    let width = width.clamp(1, (source_line.chars().count() + 1).saturating_sub(col).max(1));
+    let mut out = String::new();
+
+    out += &format!("!!! -> Error while {}: {}\n", stage, message);
+    out += &format!("{}--> {}:{}:{}\n", gutter, path.display(), line, col);
+    out += &format!("{} |\n", gutter);
+    out += &format!("{} | {}\n", line, source_line);
+    out += &format!("{} | {}{}\n", gutter, " ".repeat(col - 1), "^".repeat(width));
+
+    return out;
 }
 
-fn include(mut includes: String, include: &str) -> String {
-    if !includes.contains(&String::from(include)) {
-        includes += "#include ";
-        includes += include;
-        includes += "\n";
+/// Every error of a failed compile, all from the same stage, then how many there were.
+fn report_errors(path: &Path, content: &str, errors: &[CompileError]) {
+    for error in errors.iter() {
+        print!("{}", render_diagnostic(path, content, error.stage, &error.message, error.line, error.col, error.width));
     }
 
-    return includes;
+    println!("!!! -> Error: {} errors while {}", errors.len(), errors.first().map_or("compiling", |error| error.stage));
 }
 
-impl Generator <'_> {
-    fn generate(&mut self) -> String {
-        let mut includes = String::new();
-        let mut src = String::new();
-        src += "int main() {\n";
-
-        while self.peek(None).is_some() {
-            let stmt = self.peek(None).unwrap();
-
-            match stmt {
-                StmtType::Print(user_types) => {
-                    includes = include(includes, "<iostream>");
-                    src += "std::cout<<";
-
-                    for user_type in user_types.into_iter() {
-                        match user_type {
-                            UserType::Int(x) => {
-                                src += x.to_string().as_str();
-                                src += "<<";
-                            }
-                            UserType::String(x) => {
-                                src += x.as_str();
-                                src += "<<";
-                            }
-                        }
-                    }
-
-                    src += "std::endl;\n";
-                }
-                StmtType::Let { key, value } => {
-                    match key {
-                        TokenType::UserType(key_ut) => {
-                            match key_ut {
-                                UserType::String(key_string) => {
-                                    match value {
-                                        TokenType::UserType(value_ut) => {
-                                            match value_ut {
-                                                UserType::String(value_string) => {
-                                                    includes = include(includes, "<string>");
-                                                    src += "std::string ";
-                                                    src += key_string;
-                                                    src += "=";
-                                                    src += value_string;
-                                                    src += ";\n";
-                                                }
-                                                UserType::Int(value_int) => {
-                                                    src += "int ";
-                                                    src += key_string;
-                                                    src += "=";
-                                                    src += value_int.to_string().as_str();
-                                                    src += ";\n";
-                                                }
-                                            }
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            }
+/// `src` with every line prefixed by its right-aligned 1-based number and a tab.
+fn number_lines(src: &str) -> String {
+    let mut out = String::new();
 
-            self.accept();
-        }
-        
-        src += "}";
+    for (number, line) in src.lines().enumerate() {
+        out += &format!("{:>6}\t{}\n", number + 1, line);
+    }
 
-        return format!("{}\n{}", includes, src);
+    // callers print the generated source without a trailing newline
+    out.pop();
+
+    return out;
+}
+
+/// The framed "user input / compiler results" banner printed after a successful compile.
+fn render_result(source: &str, generated: &str) -> String {
+    let mut out = String::new();
+
+    out += "\n";
+    out += "          ⇊     User input   ⇊\n";
+    out += "----- Zynk ----------------------\n";
+    // prefix every source line so the frame stays unambiguous
+    // even if the source itself contains a delimiter line
+    for line in source.lines() {
+        out += "| ";
+        out += line;
+        out += "\n";
     }
+    out += "----- Zynk ----------------------\n";
 
-    fn peek(self: &Self, _offset: Option<usize>) -> Option<&StmtType> {
-        let offset = if _offset.is_some() { _offset.unwrap() } else { 0 };
+    out += "\n";
+    out += "          ⇊ Compiler results ⇊\n";
+    out += "----- C++ -----------------------\n";
+    out += generated;
+    out += "\n";
+    out += "----- C++ -----------------------\n";
 
-        if self.index + offset < self.stmts.len() {
-            let stmt = self.stmts.index(self.index);
-            return Some(stmt);
-        } else {
-            return None;
+    return out;
+}
+
+/// `--eval` source, with a lone expression like `2 + 3 * 4` wrapped in a print so its value shows.
+fn eval_source(source: &String, keywords: &HashMap<String, String>) -> String {
+    let mut tokenizer = Tokenizer::new(source);
+    tokenizer.keywords = keywords.clone();
+
+    // anything that doesn't tokenize is left for the real run to report
+    if let TokenizerError::Ok = tokenizer.run() {
+        if let Some(first) = tokenizer.tokens.first() {
+            if matches!(first.kind, TokenType::UserType(_) | TokenType::OpenParen | TokenType::Minus | TokenType::Not | TokenType::ToString | TokenType::ToInt | TokenType::Args | TokenType::Address) {
+                return format!("print({})", source.trim());
+            }
         }
     }
 
-    fn accept(self: &mut Self) -> &StmtType {
-        self.index += 1;
-        return self.stmts.index(self.index - 1);
-    }
+    return String::from(source);
 }
 
-#[derive(ClapParser)]
-struct Cli {
-    file: PathBuf,
+/// A file is used as-is, a directory falls back to its `init.zynk`.
+fn resolve_source(path: &Path) -> Result<PathBuf, String> {
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+
+    if !path.is_dir() {
+        return Err(format!("'{}' is neither a file nor a directory", path.display()));
+    }
+
+    // canonicalize first so trailing separators (`dir\` on Windows) don't end up in the joined path
+    let Ok(mut file) = path.canonicalize() else {
+        return Err(format!("could not resolve directory '{}'", path.display()));
+    };
+    file.push("init.zynk");
+
+    return Ok(file);
 }
 
 fn main() {
-    #[allow(unused_assignments)]
-    let mut content = String::new();
-    let mut cli = Cli::parse();
-
-    if cli.file.is_file() {
-        content = read_to_string(cli.file).unwrap();
-    } else {
-        cli.file.push("init.zynk");
-        content = read_to_string(cli.file).unwrap();
+    // codegen recurses once per operator, so a `1 + 1 + ...` thousands of terms long needs more
+    // than the main thread's stack, the memory is only committed as it's used
+    let compiler = std::thread::Builder::new().stack_size(256 * 1024 * 1024).spawn(|| {
+        // setup errors (missing file, bad keyword map, ...) read like every other error
+        if let Err(err) = build(Cli::parse()) {
+            println!("!!! -> Error: {}", err);
+            std::process::exit(1);
+        }
+    });
+
+    // a panic has already been reported by the compiler thread
+    if compiler.expect("could not start the compiler thread").join().is_err() {
+        std::process::exit(101);
     }
+}
 
-    let mut tokenizer = Tokenizer::new(&content);
-    tokenizer.run();
+fn build(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let mut keywords = HashMap::new();
 
-    let mut parser = Parser { tokens: &tokenizer.tokens, index: 0, stmts: Vec::new() };
-    let parser_result = parser.run();
+    if let Some(path) = &cli.keyword_map {
+        let map = read_to_string(path).map_err(|err| format!("could not read keyword map '{}': {}", path.display(), err))?;
+        keywords = parse_keyword_map(&map)?;
+    }
 
-    match parser_result {
-        ParserError::Ok => {
-            println!("");
-            println!("          ⇊     User input   ⇊");
-            println!("----- Zynk ----------------------");
-            println!("{}", content);
-            println!("----- Zynk ----------------------");
+    let (file, content) = match &cli.eval {
+        Some(source) => (PathBuf::from("<eval>"), eval_source(source, &keywords)),
+        None => {
+            let file = resolve_source(cli.file.as_ref().unwrap())?;
+            let content = read_to_string(&file).map_err(|err| format!("could not read '{}': {}", file.display(), err))?;
+            (file, content)
+        }
+    };
+
+    let options = CompileOptions {
+        keywords,
+        keep_going_tokens: cli.keep_going_tokens,
+        trace: cli.trace,
+        naming: cli.naming,
+        strict: cli.strict,
+        max_depth: cli.max_depth,
+        compat: cli.compat,
+        no_std_endl: cli.no_std_endl,
+        checked_arithmetic: cli.checked_arithmetic,
+        annotate: cli.annotate,
+        preserve_comments: cli.preserve_comments,
+        tree: cli.tree || matches!(cli.emit, Some(Emit::All)),
+    };
+
+    if let Some(iterations) = cli.bench_tokenize {
+        let start = Instant::now();
+
+        for _ in 0..iterations {
+            tokenize(&content, &options);
+        }
+
+        println!("tokenized {} times, {:?} on average", iterations, start.elapsed() / iterations);
+        return Ok(());
+    }
+
+    // these only need the tokens, so they don't parse
+    if cli.emit_tokens || cli.strip_comments {
+        let tokenizer = tokenize(&content, &options);
+
+        for warning in tokenizer.warnings.iter() {
+            println!("!!! -> Warning: {}", warning);
+        }
+
+        if !tokenizer.errors.is_empty() {
+            report_errors(&file, &content, &tokenizer_errors(&tokenizer));
+
+            if let Some(path) = &cli.summary_json {
+                let summary = BuildSummary { input: &file, output: None, tokens: tokenizer.tokens.len(), stmts: 0, warnings: tokenizer.warnings.len(), success: false };
+                write_summary(path, &summary);
+            }
+
+            std::process::exit(1);
+        }
+
+        if cli.emit_tokens {
+            for token in tokenizer.tokens.iter() {
+                println!("{}:{} {:?}", token.line, token.col, token.kind);
+            }
+        } else {
+            let stripped = strip_comments(&content, &tokenizer.comments);
+
+            match &cli.output {
+                Some(output) => { std::fs::write(output, stripped).map_err(|err| format!("could not write '{}': {}", output.display(), err))?; }
+                None => { print!("{}", stripped); }
+            }
+        }
+
+        return Ok(());
+    }
+
+    let compilation = compile(&content, &options);
+
+    for warning in compilation.warnings.iter() {
+        println!("!!! -> Warning: {}", warning);
+    }
+
+    let warnings = compilation.warnings.len();
+    let too_many_warnings = cli.fail_on_warning_count.is_some_and(|limit| warnings > limit);
+
+    let finish = |cpp: &String| {
+        let mut generated = cpp.clone();
+
+        if cli.clang_format {
+            generated = clang_format(generated);
+        }
+
+        if cli.line_numbers {
+            generated = number_lines(&generated);
+        }
+
+        return generated;
+    };
+
+    let mut exit_code = 0;
+
+    match &compilation.result {
+        Ok(_) if too_many_warnings => {
+            println!("!!! -> Error: {} warnings, --fail-on-warning-count allows {}", warnings, cli.fail_on_warning_count.unwrap());
+        }
+        Ok(cpp) if matches!(cli.emit, Some(Emit::All)) => {
+            println!("----- Source --------------------");
+            for line in content.lines() {
+                println!("| {}", line);
+            }
+
+            println!("----- Tokens --------------------");
+            for token in compilation.tokens.iter() {
+                println!("{:?}", token.kind);
+            }
+
+            println!("----- AST -----------------------");
+            println!("Program");
+            print!("{}", compilation.tree.as_deref().unwrap_or(""));
 
-            let mut generator = Generator { stmts: &parser.stmts, index: 0 };
-            println!("");
-            println!("          ⇊ Compiler results ⇊");
-            println!("----- C++ -----------------------");
-            println!("{}", generator.generate());
             println!("----- C++ -----------------------");
+            println!("{}", finish(cpp));
+            println!("---------------------------------");
         }
-        ParserError::Err(err) => {
-            println!("!!! -> Error: {}", err);
+        Ok(_) if cli.tree => {
+            println!("Program");
+            print!("{}", compilation.tree.as_deref().unwrap_or(""));
+        }
+        Ok(cpp) if cli.run || cli.eval.is_some() => {
+            exit_code = run_cpp(&finish(cpp), cli.input.as_deref(), cli.time_limit.map(Duration::from_secs))?;
+        }
+        Ok(cpp) if cli.output.is_some() => {
+            let output = cli.output.as_ref().unwrap();
+            std::fs::write(output, finish(cpp) + "\n").map_err(|err| format!("could not write '{}': {}", output.display(), err))?;
+        }
+        Ok(cpp) => {
+            print!("{}", render_result(&content, &finish(cpp)));
+        }
+        Err(errors) => {
+            report_errors(&file, &content, errors);
         }
     }
+
+    let success = compilation.result.is_ok() && !too_many_warnings;
+
+    if let Some(path) = &cli.summary_json {
+        let summary = BuildSummary { input: &file, output: cli.output.as_deref().filter(|_| success), tokens: compilation.tokens.len(), stmts: compilation.stmts, warnings, success };
+        write_summary(path, &summary);
+    }
+
+    if !success {
+        std::process::exit(1);
+    }
+
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+
+    return Ok(());
 }
@@ -0,0 +1,1309 @@
+use std::{collections::{HashMap, HashSet}, ops::Index};
+
+use crate::{generator::{cpp_name, emit_cpp_string, Naming}, tokenizer::{Token, TokenType, UserType}};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OpType {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprType {
+    Literal(UserType),
+    Binary {
+        op: OpType,
+        lhs: Box<ExprType>,
+        rhs: Box<ExprType>,
+        // from `infer_type` when it was parsed, long chains aren't walked again for every operator
+        cpp_type: &'static str,
+    },
+    // pointer to the named variable, `address(x)`
+    Address(String),
+    // command-line argument i, `args(i)`
+    Argument(i32),
+    // `to_string(x)`, always a std::string
+    ToString(Box<ExprType>),
+    // `to_int(x)`, always an int
+    ToInt(Box<ExprType>),
+    // `not x`
+    Not(Box<ExprType>),
+    // `-x`, a negated number literal is folded into the literal instead
+    Neg(Box<ExprType>),
+}
+
+pub enum ParserError {
+    Ok,
+    Err(String)
+}
+
+#[derive(Debug)]
+pub enum StmtType <'a> {
+    Print(Vec<ExprType>),
+    // like print but with a space between arguments, `say(a, b)`
+    Say(Vec<ExprType>),
+    // like print but without the trailing newline, `write(a, b)`
+    Write(Vec<ExprType>),
+    // C++ expression printed verbatim, `raw_print("x * 2")`
+    RawPrint(String),
+    Debug(String),
+    // any condition, `expect x + 1 == 4` or `expect flag`
+    Expect {
+        condition: ExprType,
+    },
+    Let {
+        key: &'a TokenType,
+        value: ExprType,
+        // inferred while the value's variables were still in scope
+        cpp_type: &'static str,
+    },
+    // `set x to 6`, x has to be declared already
+    Assign {
+        key: String,
+        value: ExprType,
+    },
+    // every `let` after this point is declared const
+    Freeze,
+    // carries the source line it appeared on
+    Here(usize),
+    // `if x ... else ... end`, else_body is empty without an else
+    If {
+        condition: ExprType,
+        then_body: Vec<StmtType<'a>>,
+        else_body: Vec<StmtType<'a>>,
+    },
+    // `while x ... end`
+    While {
+        condition: ExprType,
+        body: Vec<StmtType<'a>>,
+    },
+}
+
+pub struct Parser <'a> {
+    tokens: &'a Vec<Token>,
+    pub stmts: Vec<StmtType<'a>>,
+    index: usize,
+    pub trace: bool,
+    // names bound by `let` so far and their inferred C++ type
+    variables: HashMap<String, &'static str>,
+    // names bound by `let` in the current block, C++ rejects declaring them again there
+    scope: HashSet<String>,
+    // names declared while frozen, these can't be `set`
+    constants: HashSet<String>,
+    // set by `freeze`, like Generator::frozen
+    frozen: bool,
+    // renaming applied in codegen, two names must not end up the same
+    pub naming: Naming,
+    // token range every statement was parsed from, nested ones included, a block before its body
+    pub spans: Vec<(usize, usize)>,
+    // every error so far and the index of the token it was found at
+    pub errors: Vec<(usize, String)>,
+    // token an error is about when that isn't the current one, taken by `recover`
+    error_at: Option<usize>,
+    pub warnings: Vec<String>,
+    // turn warnings about likely mistakes into errors
+    pub strict: bool,
+    // blocks currently open, the recursion stops at `max_depth` instead of overflowing the stack
+    depth: usize,
+    pub max_depth: usize,
+    // an undeclared bare word given to print/say/write is its own text, as before identifiers existed
+    pub compat: bool,
+}
+
+impl <'a> Parser <'a> {
+    pub fn new(tokens: &'a Vec<Token>) -> Parser<'a> {
+        return Parser {
+            tokens,
+            stmts: Vec::new(),
+            index: 0,
+            trace: false,
+            variables: HashMap::new(),
+            scope: HashSet::new(),
+            constants: HashSet::new(),
+            frozen: false,
+            naming: Naming::Preserve,
+            spans: Vec::new(),
+            errors: Vec::new(),
+            error_at: None,
+            warnings: Vec::new(),
+            strict: false,
+            depth: 0,
+            max_depth: 128,
+            compat: false,
+        }
+    }
+
+    fn parse_stmt(self: &mut Self) -> ParserError {
+        if TokenType::Print == *self.peek(None).unwrap() {
+            self.trace("branch: print statement");
+            self.accept();
+
+            match self.parse_print_args("print") {
+                Ok(exprs) => { self.stmts.push(StmtType::Print(exprs)); }
+                Err(err) => { return ParserError::Err(err); }
+            }
+        } else if TokenType::Say == *self.peek(None).unwrap() {
+            self.trace("branch: say statement");
+            self.accept();
+
+            match self.parse_print_args("say") {
+                Ok(exprs) => { self.stmts.push(StmtType::Say(exprs)); }
+                Err(err) => { return ParserError::Err(err); }
+            }
+        } else if TokenType::Write == *self.peek(None).unwrap() {
+            self.trace("branch: write statement");
+            let write_index = self.index;
+            self.accept();
+
+            match self.parse_print_args("write") {
+                Ok(exprs) if exprs.is_empty() => {
+                    // without the newline there'd be nothing left to output
+                    self.error_at = Some(write_index);
+                    return ParserError::Err(String::from("write statement needs at least one value"));
+                }
+                Ok(exprs) => { self.stmts.push(StmtType::Write(exprs)); }
+                Err(err) => { return ParserError::Err(err); }
+            }
+        } else if TokenType::RawPrint == *self.peek(None).unwrap() {
+            self.trace("branch: raw_print statement");
+            self.accept();
+
+            if self.peek(None).is_some() && TokenType::OpenParen == *self.peek(None).unwrap() {
+                self.accept();
+
+                // no type checking or symbol lookup, the string is trusted to be valid C++
+                let Some(TokenType::UserType(UserType::String(expr))) = self.peek(None) else {
+                    return ParserError::Err(String::from("Expected string with a C++ expression inside raw_print statement"))
+                };
+                let expr = expr.clone();
+                self.accept();
+
+                if self.peek(None).is_some() && TokenType::CloseParen == *self.peek(None).unwrap() {
+                    self.accept();
+
+                    self.stmts.push(StmtType::RawPrint(expr));
+                } else {
+                    return ParserError::Err(String::from("Expected ')' to end raw_print statement"))
+                }
+            } else {
+                return ParserError::Err(String::from("Expected '(' to start raw_print statement"))
+            }
+        } else if TokenType::Debug == *self.peek(None).unwrap() {
+            self.trace("branch: debug statement");
+            self.accept();
+
+            if self.peek(None).is_some() && TokenType::OpenParen == *self.peek(None).unwrap() {
+                self.accept();
+
+                let Some(TokenType::UserType(UserType::Identifier(name))) = self.peek(None) else {
+                    return ParserError::Err(String::from("Expected variable name inside debug statement"))
+                };
+
+                if !self.variables.contains_key(name) {
+                    return ParserError::Err(format!("debug of undeclared variable '{}'", name))
+                }
+
+                let name = name.clone();
+                self.accept();
+
+                if self.peek(None).is_some() && TokenType::CloseParen == *self.peek(None).unwrap() {
+                    self.accept();
+
+                    self.stmts.push(StmtType::Debug(name));
+                } else {
+                    return ParserError::Err(String::from("Expected ')' to end debug statement"))
+                }
+            } else {
+                return ParserError::Err(String::from("Expected '(' to start debug statement"))
+            }
+        } else if TokenType::Expect == *self.peek(None).unwrap() {
+            self.trace("branch: expect statement");
+            self.accept();
+
+            if !self.at_expr() {
+                return ParserError::Err(String::from("Expected value after 'expect'"))
+            }
+
+            let condition_index = self.index;
+            let condition = match self.parse_expr() {
+                Ok(condition) => condition,
+                Err(err) => { return ParserError::Err(err); }
+            };
+
+            self.check_condition(&condition, condition_index, "expect");
+            self.stmts.push(StmtType::Expect { condition });
+        } else if TokenType::Freeze == *self.peek(None).unwrap() {
+            self.trace("branch: freeze directive");
+            self.accept();
+
+            self.frozen = true;
+            self.stmts.push(StmtType::Freeze);
+        } else if let TokenType::Here(line) = *self.peek(None).unwrap() {
+            self.trace("branch: here statement");
+            self.accept();
+
+            self.stmts.push(StmtType::Here(line));
+        } else if TokenType::CheckType == *self.peek(None).unwrap() {
+            self.trace("branch: check_type directive");
+            self.accept();
+
+            let Some(TokenType::UserType(UserType::Identifier(name))) = self.peek(None) else {
+                return ParserError::Err(String::from("Expected variable name after 'check_type'"))
+            };
+            let name = name.clone();
+            self.accept();
+
+            if self.peek(None).is_some() && TokenType::Is == *self.peek(None).unwrap() {
+                self.accept();
+            } else {
+                return ParserError::Err(String::from("Expected 'is' after variable name"))
+            }
+
+            let Some(TokenType::UserType(UserType::Identifier(expected))) = self.peek(None) else {
+                return ParserError::Err(String::from("Expected type name after 'is'"))
+            };
+            let expected = expected.clone();
+            self.accept();
+
+            if !["int", "float", "string", "bool"].contains(&expected.as_str()) {
+                return ParserError::Err(format!("unknown type '{}', expected int, float, string or bool", expected))
+            }
+
+            // purely a compile-time check, nothing is generated
+            let Some(found) = self.variables.get(&name) else {
+                return ParserError::Err(format!("check_type of undeclared variable '{}'", name))
+            };
+
+            if zynk_type(found) != expected {
+                return ParserError::Err(format!("check_type failed: '{}' is {}, not {}", name, zynk_type(found), expected))
+            }
+        } else if matches!(self.peek(None).unwrap(), TokenType::If | TokenType::While) && self.depth >= self.max_depth {
+            self.trace("branch: block nested too deep");
+            self.errors.push((self.index, format!("nesting too deep, at most {} levels are allowed", self.max_depth)));
+            self.skip_block();
+        } else if TokenType::If == *self.peek(None).unwrap() {
+            self.trace("branch: if statement");
+            let keyword_index = self.index;
+            self.accept();
+
+            if !self.at_expr() {
+                return ParserError::Err(String::from("Expected condition after 'if'"))
+            }
+
+            let condition_index = self.index;
+            let condition = match self.parse_expr() {
+                Ok(condition) => condition,
+                Err(err) => { return ParserError::Err(err); }
+            };
+
+            self.check_condition(&condition, condition_index, "if");
+
+            let then_body = match self.parse_block(&[TokenType::Else, TokenType::End]) {
+                Ok(body) => body,
+                Err(err) => { return ParserError::Err(err); }
+            };
+
+            self.check_block(&then_body, keyword_index, "if");
+
+            let mut else_body: Vec<StmtType> = Vec::new();
+
+            if TokenType::Else == *self.peek(None).unwrap() {
+                let else_index = self.index;
+                self.accept();
+
+                else_body = match self.parse_block(&[TokenType::End]) {
+                    Ok(body) => body,
+                    Err(err) => { return ParserError::Err(err); }
+                };
+
+                self.check_block(&else_body, else_index, "else");
+
+                // the generator drops it, the else can never run
+                if constant_condition(&condition) == Some(true) {
+                    let (line, _) = self.position(else_index);
+                    self.warnings.push(format!("unreachable else branch on line {}", line));
+                }
+            }
+
+            // parse_block only returns once it's looking at a terminator
+            self.accept();
+
+            self.stmts.push(StmtType::If { condition, then_body, else_body });
+        } else if TokenType::While == *self.peek(None).unwrap() {
+            self.trace("branch: while statement");
+            let keyword_index = self.index;
+            self.accept();
+
+            if !self.at_expr() {
+                return ParserError::Err(String::from("Expected condition after 'while'"))
+            }
+
+            let condition_index = self.index;
+            let condition = match self.parse_expr() {
+                Ok(condition) => condition,
+                Err(err) => { return ParserError::Err(err); }
+            };
+
+            self.check_condition(&condition, condition_index, "while");
+
+            let body = match self.parse_block(&[TokenType::End]) {
+                Ok(body) => body,
+                Err(err) => { return ParserError::Err(err); }
+            };
+
+            self.check_block(&body, keyword_index, "while");
+
+            self.accept();
+
+            self.stmts.push(StmtType::While { condition, body });
+        } else if matches!(self.peek(None).unwrap(), TokenType::Else | TokenType::End) {
+            let keyword = if TokenType::Else == *self.peek(None).unwrap() { "else" } else { "end" };
+
+            return ParserError::Err(format!("'{}' without a matching 'if'", keyword))
+        } else if TokenType::Set == *self.peek(None).unwrap() {
+            self.trace("branch: set statement");
+            self.accept();
+
+            let Some(TokenType::UserType(UserType::Identifier(name))) = self.peek(None) else {
+                return ParserError::Err(String::from("Expected variable name after 'set'"))
+            };
+            let name = name.clone();
+            let key_index = self.index;
+
+            let Some(key_type) = self.variables.get(&name).copied() else {
+                return ParserError::Err(format!("set of undeclared variable '{}', declare it with 'let' first", name))
+            };
+
+            if self.constants.contains(&name) {
+                return ParserError::Err(format!("can't set '{}', it was declared after 'freeze'", name))
+            }
+
+            self.accept();
+
+            if self.peek(None).is_some() && TokenType::To == *self.peek(None).unwrap() {
+                self.accept();
+            } else {
+                return ParserError::Err(String::from("Expected 'to' after variable name"))
+            }
+
+            if !self.at_expr() {
+                return ParserError::Err(String::from("Expected value after 'to'"))
+            }
+
+            let value = match self.parse_expr() {
+                Ok(value) => value,
+                Err(err) => { return ParserError::Err(err); }
+            };
+
+            // numbers convert into each other in C++, strings and numbers don't
+            let value_type = infer_type(&value, &self.variables);
+
+            if key_type != "auto" && value_type != "auto" && (key_type == "std::string") != (value_type == "std::string") {
+                self.error_at = Some(key_index);
+                return ParserError::Err(format!("can't set {} variable '{}' to a {}", zynk_type(key_type), name, zynk_type(value_type)))
+            }
+
+            self.stmts.push(StmtType::Assign { key: name, value });
+        } else if TokenType::Let == *self.peek(None).unwrap() {
+            self.trace("branch: let statement");
+            self.accept();
+
+            // index of every name token, so errors can point back at it
+            let mut key_tokens: Vec<usize> = Vec::new();
+
+            while self.peek(None).is_some() && matches!(self.peek(None).unwrap(), TokenType::UserType(UserType::Identifier(_))) {
+                key_tokens.push(self.index);
+                self.accept();
+
+                if self.peek(None).is_some() && TokenType::Comma == *self.peek(None).unwrap() {
+                    // found comma, gonna keep looking for names
+                    self.accept();
+                } else {
+                    break;
+                }
+            }
+
+            if key_tokens.is_empty() {
+                return ParserError::Err(String::from("Expected variable name after 'let'"))
+            }
+
+            if self.peek(None).is_some() && TokenType::To == *self.peek(None).unwrap() {
+                self.accept();
+
+                let mut values: Vec<ExprType> = Vec::new();
+
+                while self.at_expr() {
+                    match self.parse_expr() {
+                        Ok(value) => { values.push(value); }
+                        Err(err) => { return ParserError::Err(err); }
+                    }
+
+                    if self.peek(None).is_some() && TokenType::Comma == *self.peek(None).unwrap() {
+                        // found comma, gonna keep looking for values
+                        self.accept();
+                    } else {
+                        break;
+                    }
+                }
+
+                if values.is_empty() {
+                    return ParserError::Err(String::from("Expected value after 'to'"))
+                }
+
+                // `let x to 1 2` would otherwise leave the 2 behind
+                if self.at_expr() {
+                    return ParserError::Err(String::from("Expected ',' between values"))
+                }
+
+                if values.len() != key_tokens.len() {
+                    return ParserError::Err(format!("expected {} values, found {}", key_tokens.len(), values.len()))
+                }
+
+                let tokens = self.tokens;
+                let names: Vec<&String> = key_tokens.iter().filter_map(|index| match &tokens.index(*index).kind {
+                    TokenType::UserType(UserType::Identifier(name)) => Some(name),
+                    _ => None,
+                }).collect();
+
+                for (position, (key_index, value)) in key_tokens.into_iter().zip(values).enumerate() {
+                    let key_token = &tokens.index(key_index).kind;
+                    let mut value_type = "auto";
+
+                    if let TokenType::UserType(UserType::Identifier(name)) = key_token {
+                        let cpp = cpp_name(name, self.naming);
+
+                        // `let x to x + 1` in a block would read the new, uninitialised x in C++
+                        if let Some(used) = names[..=position].iter().find(|used| mentions(&value, used)) {
+                            self.error_at = Some(key_index);
+                            return ParserError::Err(format!("the value of '{}' can't use '{}', in C++ it would read the '{}' declared here instead of the earlier one", name, used, used))
+                        }
+
+                        if let Some(other) = self.variables.keys().find(|other| *other != name && cpp_name(other, self.naming) == cpp) {
+                            self.error_at = Some(key_index);
+                            return ParserError::Err(format!("'{}' and '{}' would both be named '{}' in C++", other, name, cpp))
+                        }
+
+                        if self.scope.contains(name) {
+                            self.error_at = Some(key_index);
+                            return ParserError::Err(format!("'{}' is already declared, use 'set {} to ...' to change it", name, name))
+                        }
+
+                        // legal C++, but every later use in the block silently means the new one
+                        if self.variables.contains_key(name) {
+                            if self.strict {
+                                self.errors.push((key_index, format!("variable '{}' shadows outer declaration", name)));
+                            } else {
+                                let (line, _) = self.position(key_index);
+                                self.warnings.push(format!("variable '{}' shadows outer declaration on line {}", name, line));
+                            }
+                        }
+
+                        value_type = infer_type(&value, &self.variables);
+                        self.variables.insert(name.clone(), value_type);
+                        self.scope.insert(name.clone());
+
+                        // redeclaring, even in a nested block, can't lift the freeze as it never ends
+                        if self.frozen {
+                            self.constants.insert(name.clone());
+                        }
+                    }
+
+                    let stmt = StmtType::Let { key: key_token, value, cpp_type: value_type };
+                    self.stmts.push(stmt);
+                }
+            } else {
+                return ParserError::Err(String::from("Expected 'to' after variable name"))
+            }
+        } else {
+            self.trace("branch: unexpected token");
+            return ParserError::Err(String::from("Expected a statement"));
+        }
+
+        return ParserError::Ok;
+    }
+
+    /// The parenthesized, comma separated values of a print-like statement.
+    fn parse_print_args(self: &mut Self, name: &str) -> Result<Vec<ExprType>, String> {
+        let mut exprs: Vec<ExprType> = Vec::new();
+
+        if self.peek(None).is_some() && TokenType::OpenParen == *self.peek(None).unwrap() {
+            self.accept();
+        } else {
+            return Err(format!("Expected '(' to start {} statement", name));
+        }
+
+        while self.at_expr() {
+            let bare_word = match (self.peek(None), self.peek(Some(1))) {
+                (Some(TokenType::UserType(UserType::Identifier(word))), Some(TokenType::Comma | TokenType::CloseParen)) if self.compat && !self.variables.contains_key(word) => Some(word.clone()),
+                _ => None,
+            };
+
+            if let Some(word) = bare_word {
+                self.trace("branch: compat bare word");
+                self.accept();
+                exprs.push(ExprType::Literal(UserType::String(word)));
+            } else {
+                exprs.push(self.parse_expr()?);
+            }
+
+            if self.peek(None).is_some() && TokenType::Comma == *self.peek(None).unwrap() {
+                // found comma, gonna keep looking for values
+                self.accept();
+            } else {
+                // didn't find comma, gonna stop now
+                break;
+            }
+        }
+
+        if self.peek(None).is_some() && TokenType::OpenParen == *self.peek(None).unwrap() {
+            return Err(format!("Expected ',' or ')' after {} argument, found '('", name));
+        }
+
+        if self.peek(None).is_some() && TokenType::CloseParen == *self.peek(None).unwrap() {
+            self.accept();
+        } else {
+            return Err(format!("Expected ')' to end {} statement", name));
+        }
+
+        return Ok(exprs);
+    }
+
+    /// Statements up to one of `terminators`, which is left unconsumed.
+    /// Variables declared inside go out of scope at the end, like in C++.
+    fn parse_block(self: &mut Self, terminators: &[TokenType]) -> Result<Vec<StmtType<'a>>, String> {
+        let outer_stmts = std::mem::take(&mut self.stmts);
+        let outer_variables = self.variables.clone();
+        let outer_scope = std::mem::take(&mut self.scope);
+        let outer_constants = self.constants.clone();
+        let mut error = None;
+        self.depth += 1;
+
+        loop {
+            match self.peek(None) {
+                None => {
+                    error = Some(String::from("Expected 'end' to close block"));
+                    break;
+                }
+                Some(token) if terminators.contains(token) => { break; }
+                Some(_) => {}
+            }
+
+            // errors in the body are recovered from here so the block itself stays intact
+            let start = self.index;
+
+            if let ParserError::Err(err) = self.parse_spanned_stmt() {
+                self.recover(start, err);
+            }
+        }
+
+        let body = std::mem::replace(&mut self.stmts, outer_stmts);
+        self.depth -= 1;
+        self.variables = outer_variables;
+        self.scope = outer_scope;
+        self.constants = outer_constants;
+
+        match error {
+            Some(err) => { return Err(err); }
+            None => { return Ok(body); }
+        }
+    }
+
+    /// Skips an `if`/`while` up to and including its matching `end` without parsing (or recursing into) it.
+    fn skip_block(self: &mut Self) {
+        let mut open = 0;
+
+        while let Some(token) = self.peek(None) {
+            match token {
+                TokenType::If | TokenType::While => { open += 1; }
+                TokenType::End => { open -= 1; }
+                _ => {}
+            }
+
+            self.accept();
+
+            if open == 0 {
+                break;
+            }
+        }
+    }
+
+    /// An empty body is almost certainly a mistake, a warning or with `strict` an error at `keyword_index`.
+    /// Either way the statement itself is fine, so parsing carries on.
+    fn check_block(self: &mut Self, body: &[StmtType], keyword_index: usize, keyword: &str) {
+        // an error inside the body already explains why it ended up empty
+        if !body.is_empty() || self.errors.iter().any(|(index, _)| *index > keyword_index) {
+            return;
+        }
+
+        if self.strict {
+            self.errors.push((keyword_index, format!("empty block in '{}'", keyword)));
+        } else {
+            let (line, _) = self.position(keyword_index);
+            self.warnings.push(format!("empty block in '{}' on line {}", keyword, line));
+        }
+    }
+
+    /// A std::string doesn't convert to bool in C++. Like `check_block` the error doesn't stop
+    /// the block from being parsed, so its `end` isn't reported as stray.
+    fn check_condition(self: &mut Self, condition: &ExprType, condition_index: usize, keyword: &str) {
+        if infer_type(condition, &self.variables) == "std::string" {
+            self.errors.push((condition_index, format!("'{}' condition can't be a string", keyword)));
+        }
+
+        // `if x = 5`, the classic typo for `==`, the rest is read as if it were one
+        if self.peek(None).is_some() && TokenType::Equal == *self.peek(None).unwrap() {
+            self.errors.push((self.index, format!("'=' in '{}' condition, did you mean '=='?", keyword)));
+            self.accept();
+
+            if self.at_expr() {
+                if let Err(err) = self.parse_expr() {
+                    let index = self.error_at.take().unwrap_or(self.index);
+                    self.errors.push((index, err));
+                }
+            }
+        }
+    }
+
+    fn at_expr(self: &Self) -> bool {
+        return self.peek(None).is_some() && matches!(self.peek(None).unwrap(), TokenType::UserType(_) | TokenType::OpenParen | TokenType::Address | TokenType::Args | TokenType::ToString | TokenType::ToInt | TokenType::Not | TokenType::Minus);
+    }
+
+    fn parse_expr(self: &mut Self) -> Result<ExprType, String> {
+        return self.parse_binary(0);
+    }
+
+    /// Precedence climbing, every operator is left associative.
+    fn parse_binary(self: &mut Self, min_precedence: u8) -> Result<ExprType, String> {
+        let mut lhs = self.parse_primary()?;
+
+        while let Some(op) = self.peek_op() {
+            if op.precedence() < min_precedence {
+                break;
+            }
+
+            self.trace("branch: binary operator");
+            let op_index = self.index;
+            self.accept();
+
+            if !self.at_expr() {
+                return Err(format!("Expected value after '{}'", op.symbol()));
+            }
+
+            let mut rhs = self.parse_binary(op.precedence() + 1)?;
+            let lhs_literal = matches!(lhs, ExprType::Literal(UserType::String(_)));
+            let rhs_literal = matches!(rhs, ExprType::Literal(UserType::String(_)));
+            let (mut lhs_type, mut rhs_type) = (infer_type(&lhs, &self.variables), infer_type(&rhs, &self.variables));
+
+            // `"count: " + count` stringifies the number side, same as wrapping it in to_string
+            if op == OpType::Add && lhs_type == "std::string" && ["int", "double", "bool"].contains(&rhs_type) {
+                rhs = ExprType::ToString(Box::new(rhs));
+                rhs_type = "std::string";
+            } else if op == OpType::Add && rhs_type == "std::string" && ["int", "double", "bool"].contains(&lhs_type) {
+                lhs = ExprType::ToString(Box::new(lhs));
+                lhs_type = "std::string";
+            }
+
+            let (lhs_string, rhs_string) = (lhs_type == "std::string", rhs_type == "std::string");
+
+            let error = if op.is_logical() {
+                if lhs_string || rhs_string { Some(format!("can't apply '{}' to a string", op.symbol())) } else { None }
+            } else if op.is_comparison() {
+                if lhs_literal && rhs_literal {
+                    // two C++ string literals would compare as pointers
+                    Some(format!("can't apply '{}' to two string literals", op.symbol()))
+                } else if lhs_type != "auto" && rhs_type != "auto" && lhs_string != rhs_string {
+                    Some(format!("can't compare {} with {}", zynk_type(lhs_type), zynk_type(rhs_type)))
+                } else {
+                    None
+                }
+            } else if op == OpType::Add && lhs_string && rhs_string {
+                // std::string concatenation, as long as one side isn't a bare C++ literal
+                if lhs_literal && rhs_literal { Some(String::from("can't add two string literals")) } else { None }
+            } else if lhs_string || rhs_string {
+                let (lhs_name, rhs_name) = (zynk_type(lhs_type), zynk_type(rhs_type));
+                Some(format!("can't apply '{}' to {} and {}", op.symbol(), lhs_name, rhs_name))
+            } else {
+                None
+            };
+
+            if let Some(error) = error {
+                self.error_at = Some(op_index);
+                return Err(error);
+            }
+
+            let cpp_type = binary_type(op, lhs_type, rhs_type);
+            lhs = ExprType::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs), cpp_type };
+        }
+
+        return Ok(lhs);
+    }
+
+    fn peek_op(self: &Self) -> Option<OpType> {
+        match self.peek(None) {
+            Some(TokenType::Plus) => { return Some(OpType::Add); }
+            Some(TokenType::Minus) => { return Some(OpType::Sub); }
+            Some(TokenType::Star) => { return Some(OpType::Mul); }
+            Some(TokenType::Slash) => { return Some(OpType::Div); }
+            Some(TokenType::EqualEqual) => { return Some(OpType::Eq); }
+            Some(TokenType::NotEqual) => { return Some(OpType::Ne); }
+            Some(TokenType::Less) => { return Some(OpType::Lt); }
+            Some(TokenType::Greater) => { return Some(OpType::Gt); }
+            Some(TokenType::LessEqual) => { return Some(OpType::Le); }
+            Some(TokenType::GreaterEqual) => { return Some(OpType::Ge); }
+            Some(TokenType::And) => { return Some(OpType::And); }
+            Some(TokenType::Or) => { return Some(OpType::Or); }
+            _ => { return None; }
+        }
+    }
+
+    /// A literal, an identifier, a parenthesized expression or a builtin call. Parens, `not`, `-`
+    /// and conversions recurse, so they count against `max_depth` like blocks do.
+    fn parse_primary(self: &mut Self) -> Result<ExprType, String> {
+        let nests = matches!(self.peek(None), Some(TokenType::OpenParen | TokenType::Not | TokenType::Minus | TokenType::ToString | TokenType::ToInt));
+
+        if !nests {
+            return self.parse_nested_primary();
+        }
+
+        if self.depth >= self.max_depth {
+            return Err(format!("nesting too deep, at most {} levels are allowed", self.max_depth));
+        }
+
+        self.depth += 1;
+        let result = self.parse_nested_primary();
+        self.depth -= 1;
+
+        return result;
+    }
+
+    fn parse_nested_primary(self: &mut Self) -> Result<ExprType, String> {
+        match self.peek(None) {
+            Some(TokenType::Address) => { return self.parse_address(); }
+            Some(TokenType::Args) => { return self.parse_args(); }
+            Some(TokenType::ToString) | Some(TokenType::ToInt) => { return self.parse_conversion(); }
+            Some(TokenType::Minus) => {
+                self.trace("branch: unary minus");
+                let minus_index = self.index;
+                self.accept();
+
+                if !self.at_expr() {
+                    return Err(String::from("Expected value after '-'"));
+                }
+
+                // folded before the literal's own range check, which `-2147483648` wouldn't pass
+                if let Some(TokenType::UserType(UserType::Int(x))) = self.peek(None) {
+                    let x = *x;
+                    self.accept();
+
+                    return Ok(ExprType::Literal(UserType::Int(-x)));
+                }
+
+                // binds tighter than any binary operator, `-x * 2` is `(-x) * 2`
+                let operand = self.parse_primary()?;
+
+                match operand {
+                    // the folded value needs the literal's range check too, `- -2147483648` is one past it
+                    ExprType::Literal(UserType::Int(x)) if -x > i32::MAX as i64 => {
+                        self.error_at = Some(minus_index);
+                        return Err(format!("integer {} does not fit in i32 (at most {})", -x, i32::MAX));
+                    }
+                    // `-5` and `- 5` are both just the literal -5
+                    ExprType::Literal(UserType::Int(x)) => { return Ok(ExprType::Literal(UserType::Int(-x))); }
+                    ExprType::Literal(UserType::Float(x)) => { return Ok(ExprType::Literal(UserType::Float(-x))); }
+                    _ => {}
+                }
+
+                if infer_type(&operand, &self.variables) == "std::string" {
+                    self.error_at = Some(minus_index);
+                    return Err(String::from("can't apply '-' to a string"));
+                }
+
+                return Ok(ExprType::Neg(Box::new(operand)));
+            }
+            Some(TokenType::Not) => {
+                self.trace("branch: not operator");
+                self.accept();
+
+                if !self.at_expr() {
+                    return Err(String::from("Expected value after 'not'"));
+                }
+
+                // like Python, `not a < b` negates the whole comparison but stops at `and`/`or`
+                let operand = self.parse_binary(OpType::Eq.precedence())?;
+
+                if infer_type(&operand, &self.variables) == "std::string" {
+                    return Err(String::from("can't apply 'not' to a string"));
+                }
+
+                return Ok(ExprType::Not(Box::new(operand)));
+            }
+            Some(TokenType::OpenParen) => {
+                let paren_index = self.index;
+                self.accept();
+
+                let expr = self.parse_expr()?;
+
+                // `print((1, 2)`, a doubled paren swallowing the argument list
+                if self.peek(None).is_some() && TokenType::Comma == *self.peek(None).unwrap() {
+                    self.error_at = Some(paren_index);
+                    return Err(String::from("'(' isn't closed before the ',', parentheses group a single value and not a list of arguments"));
+                }
+
+                if self.peek(None).is_some() && TokenType::CloseParen == *self.peek(None).unwrap() {
+                    self.accept();
+                } else {
+                    return Err(String::from("Expected ')' to close parenthesized expression"));
+                }
+
+                return Ok(expr);
+            }
+            Some(TokenType::UserType(UserType::Identifier(name))) => {
+                // bare words are variable references, quoted text is a string literal
+                if !self.variables.contains_key(name) {
+                    return Err(format!("use of undeclared variable '{}'", name));
+                }
+
+                let name = name.clone();
+                self.accept();
+
+                return Ok(ExprType::Literal(UserType::Identifier(name)));
+            }
+            Some(TokenType::UserType(UserType::Int(x))) if *x > i32::MAX as i64 => {
+                return Err(format!("integer literal {} does not fit in i32 (at most {})", x, i32::MAX));
+            }
+            Some(TokenType::UserType(UserType::String(x))) => {
+                let mut x = x.clone();
+                self.accept();
+
+                // adjacent literals are one string, like in C, `"a" "b"` is "ab"
+                while let Some(TokenType::UserType(UserType::String(next))) = self.peek(None) {
+                    x += next;
+                    self.accept();
+                }
+
+                return Ok(ExprType::Literal(UserType::String(x)));
+            }
+            Some(TokenType::UserType(user_type)) => {
+                let user_type = user_type.clone();
+                self.accept();
+
+                return Ok(ExprType::Literal(user_type));
+            }
+            _ => { return Err(String::from("Expected value")); }
+        }
+    }
+
+    fn parse_conversion(self: &mut Self) -> Result<ExprType, String> {
+        let to_int = TokenType::ToInt == *self.peek(None).unwrap();
+        let name = if to_int { "to_int" } else { "to_string" };
+        self.trace(if to_int { "branch: to_int builtin" } else { "branch: to_string builtin" });
+        self.accept();
+
+        if self.peek(None).is_some() && TokenType::OpenParen == *self.peek(None).unwrap() {
+            self.accept();
+        } else {
+            return Err(format!("Expected '(' after '{}'", name));
+        }
+
+        if !self.at_expr() {
+            return Err(format!("Expected value inside {}", name));
+        }
+
+        let argument_index = self.index;
+        let argument = self.parse_expr()?;
+
+        match (infer_type(&argument, &self.variables), to_int) {
+            ("int" | "double", false) => {}
+            ("std::string", true) => {
+                if let ExprType::Literal(UserType::String(x)) = &argument {
+                    if x.trim().parse::<i32>().is_err() {
+                        self.warnings.push(format!("to_int argument {} is not a number", emit_cpp_string(x)));
+                    }
+                }
+            }
+            (found, _) => {
+                let expected = if to_int { "a string" } else { "a number" };
+                self.error_at = Some(argument_index);
+                return Err(format!("{} expects {}, found {}", name, expected, zynk_type(found)));
+            }
+        }
+
+        if self.peek(None).is_some() && TokenType::CloseParen == *self.peek(None).unwrap() {
+            self.accept();
+        } else {
+            return Err(format!("Expected ')' to end {}", name));
+        }
+
+        if to_int {
+            return Ok(ExprType::ToInt(Box::new(argument)));
+        } else {
+            return Ok(ExprType::ToString(Box::new(argument)));
+        }
+    }
+
+    fn parse_address(self: &mut Self) -> Result<ExprType, String> {
+        self.trace("branch: address builtin");
+        self.accept();
+
+        if self.peek(None).is_some() && TokenType::OpenParen == *self.peek(None).unwrap() {
+            self.accept();
+        } else {
+            return Err(String::from("Expected '(' after 'address'"));
+        }
+
+        let Some(TokenType::UserType(UserType::Identifier(name))) = self.peek(None) else {
+            return Err(String::from("Expected variable name inside address"));
+        };
+
+        if !self.variables.contains_key(name) {
+            return Err(format!("address of undeclared variable '{}'", name));
+        }
+
+        let address = ExprType::Address(name.clone());
+        self.accept();
+
+        if self.peek(None).is_some() && TokenType::CloseParen == *self.peek(None).unwrap() {
+            self.accept();
+        } else {
+            return Err(String::from("Expected ')' to end address"));
+        }
+
+        return Ok(address);
+    }
+
+    fn parse_args(self: &mut Self) -> Result<ExprType, String> {
+        self.trace("branch: args builtin");
+        self.accept();
+
+        if self.peek(None).is_some() && TokenType::OpenParen == *self.peek(None).unwrap() {
+            self.accept();
+        } else {
+            return Err(String::from("Expected '(' after 'args'"));
+        }
+
+        let Some(TokenType::UserType(UserType::Int(index))) = self.peek(None) else {
+            return Err(String::from("Expected argument index inside args"));
+        };
+
+        let Ok(index) = i32::try_from(*index) else {
+            return Err(format!("argument index {} does not fit in i32 (at most {})", index, i32::MAX));
+        };
+
+        let argument = ExprType::Argument(index);
+        self.accept();
+
+        if self.peek(None).is_some() && TokenType::CloseParen == *self.peek(None).unwrap() {
+            self.accept();
+        } else {
+            return Err(String::from("Expected ')' to end args"));
+        }
+
+        return Ok(argument);
+    }
+
+    pub fn run(self: &mut Self) -> ParserError {
+        while self.peek(None).is_some() {
+            let start = self.index;
+
+            if let ParserError::Err(err) = self.parse_spanned_stmt() {
+                self.recover(start, err);
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return ParserError::Err(format!("{} errors while parsing", self.errors.len()));
+        }
+
+        return ParserError::Ok;
+    }
+
+    /// `parse_stmt`, recording the span of every stmt it produced ahead of the spans its body added.
+    fn parse_spanned_stmt(self: &mut Self) -> ParserError {
+        let (start, slot, count) = (self.index, self.spans.len(), self.stmts.len());
+        let result = self.parse_stmt();
+        // one source statement can produce several stmts (`let a, b to 1, 2`), or none at all
+        let produced = self.stmts.len() - count;
+
+        if produced == 0 {
+            self.spans.truncate(slot);
+        } else {
+            self.spans.splice(slot..slot, std::iter::repeat_n((start, self.index), produced));
+        }
+
+        return result;
+    }
+
+    /// Records `err` and skips ahead to the next token that can start (or close) a statement.
+    fn recover(self: &mut Self, start: usize, err: String) {
+        let index = self.error_at.take().unwrap_or(self.index);
+        self.errors.push((index, err));
+
+        // the offending token must not be parsed again
+        if self.index == start {
+            self.accept();
+        }
+
+        while self.peek(None).is_some() && !matches!(self.peek(None).unwrap(), TokenType::Print | TokenType::Say | TokenType::Write | TokenType::RawPrint | TokenType::Debug | TokenType::Expect | TokenType::Freeze | TokenType::Here(_) | TokenType::CheckType | TokenType::If | TokenType::Else | TokenType::End | TokenType::While | TokenType::Set | TokenType::Let) {
+            self.accept();
+        }
+    }
+
+    fn peek(self: &Self, _offset: Option<usize>) -> Option<&TokenType> {
+        let offset = _offset.unwrap_or(0);
+
+        if self.index + offset < self.tokens.len() {
+            let token = self.tokens.index(self.index + offset);
+            return Some(&token.kind);
+        } else {
+            return None;
+        }
+    }
+
+    /// Line and column of the token at `index`, or of the last one if it's past the end.
+    pub fn position(self: &Self, index: usize) -> (usize, usize) {
+        match self.tokens.get(index).or(self.tokens.last()) {
+            Some(token) => { return (token.line, token.col); }
+            None => { return (1, 1); }
+        }
+    }
+
+    fn accept(self: &mut Self) {
+        if self.trace {
+            eprintln!("[trace] accept {:?}", self.tokens.index(self.index).kind);
+        }
+
+        self.index += 1;
+    }
+
+    fn trace(self: &Self, message: &str) {
+        if self.trace {
+            eprintln!("[trace] {}", message);
+        }
+    }
+}
+
+impl OpType {
+    pub fn precedence(self: &Self) -> u8 {
+        match self {
+            OpType::Or => { return 1; }
+            OpType::And => { return 2; }
+            OpType::Eq | OpType::Ne | OpType::Lt | OpType::Gt | OpType::Le | OpType::Ge => { return 3; }
+            OpType::Add | OpType::Sub => { return 4; }
+            OpType::Mul | OpType::Div => { return 5; }
+        }
+    }
+
+    pub fn symbol(self: &Self) -> &'static str {
+        match self {
+            OpType::Add => { return "+"; }
+            OpType::Sub => { return "-"; }
+            OpType::Mul => { return "*"; }
+            OpType::Div => { return "/"; }
+            OpType::Eq => { return "=="; }
+            OpType::Ne => { return "!="; }
+            OpType::Lt => { return "<"; }
+            OpType::Gt => { return ">"; }
+            OpType::Le => { return "<="; }
+            OpType::Ge => { return ">="; }
+            OpType::And => { return "and"; }
+            OpType::Or => { return "or"; }
+        }
+    }
+
+    /// The C++ spelling, which only differs from `symbol` for logical operators.
+    pub fn cpp_symbol(self: &Self) -> &'static str {
+        match self {
+            OpType::And => { return "&&"; }
+            OpType::Or => { return "||"; }
+            _ => { return self.symbol(); }
+        }
+    }
+
+    pub fn is_logical(self: &Self) -> bool {
+        return matches!(self, OpType::And | OpType::Or);
+    }
+
+    pub fn is_comparison(self: &Self) -> bool {
+        return matches!(self, OpType::Eq | OpType::Ne | OpType::Lt | OpType::Gt | OpType::Le | OpType::Ge);
+    }
+}
+
+/// The C++ type `expr` evaluates to, "auto" when it can't be told from `variables`.
+pub fn infer_type(expr: &ExprType, variables: &HashMap<String, &'static str>) -> &'static str {
+    match expr {
+        ExprType::Literal(UserType::Int(_)) | ExprType::ToInt(_) => { return "int"; }
+        ExprType::Literal(UserType::Float(_)) => { return "double"; }
+        ExprType::Literal(UserType::Bool(_)) => { return "bool"; }
+        ExprType::Not(_) => { return "bool"; }
+        ExprType::Neg(x) => { return infer_type(x, variables); }
+        ExprType::Literal(UserType::String(_)) | ExprType::Argument(_) | ExprType::ToString(_) => { return "std::string"; }
+        ExprType::Literal(UserType::Identifier(name)) => { return variables.get(name).copied().unwrap_or("auto"); }
+        ExprType::Address(_) => { return "auto"; }
+        ExprType::Binary { cpp_type, .. } => { return cpp_type; }
+    }
+}
+
+/// The type of `lhs op rhs` given the types of both sides.
+fn binary_type(op: OpType, lhs: &'static str, rhs: &'static str) -> &'static str {
+    if op.is_comparison() || op.is_logical() {
+        return "bool";
+    } else if lhs == "std::string" || rhs == "std::string" {
+        // only `+` gets to mix strings, as concatenation
+        return "std::string";
+    } else if lhs == "auto" || rhs == "auto" {
+        return "auto";
+    } else if lhs == "double" || rhs == "double" {
+        return "double";
+    } else {
+        return "int";
+    }
+}
+
+/// The value of a condition that can't change at runtime, `true`, `false` and `not` of either.
+pub fn constant_condition(expr: &ExprType) -> Option<bool> {
+    match expr {
+        ExprType::Literal(UserType::Bool(x)) => { return Some(*x); }
+        ExprType::Not(x) => { return constant_condition(x).map(|x| !x); }
+        _ => { return None; }
+    }
+}
+
+/// Whether `name` is read anywhere in `expr`.
+fn mentions(expr: &ExprType, name: &str) -> bool {
+    match expr {
+        ExprType::Literal(UserType::Identifier(x)) | ExprType::Address(x) => { return x == name; }
+        ExprType::Binary { lhs, rhs, .. } => { return mentions(lhs, name) || mentions(rhs, name); }
+        ExprType::ToString(x) | ExprType::ToInt(x) | ExprType::Not(x) | ExprType::Neg(x) => { return mentions(x, name); }
+        _ => { return false; }
+    }
+}
+
+/// The Zynk spelling of a C++ type from `infer_type`.
+pub fn zynk_type(cpp_type: &str) -> &'static str {
+    match cpp_type {
+        "int" => { return "int"; }
+        "double" => { return "float"; }
+        "std::string" => { return "string"; }
+        "bool" => { return "bool"; }
+        // only `address(...)` and what's computed from it isn't given a type
+        "auto" => { return "address"; }
+        _ => { return "unknown"; }
+    }
+}
+
+fn tree_token(token: &TokenType) -> String {
+    match token {
+        TokenType::UserType(user_type) => { return format!("{:?}", user_type); }
+        _ => { return format!("{:?}", token); }
+    }
+}
+
+fn tree_expr(expr: &ExprType, depth: usize) -> String {
+    let indent = "  ".repeat(depth);
+
+    match expr {
+        ExprType::Literal(user_type) => { return format!("{}{:?}\n", indent, user_type); }
+        ExprType::Binary { op, lhs, rhs, .. } => {
+            return format!("{}Binary {:?}\n{}{}", indent, op, tree_expr(lhs, depth + 1), tree_expr(rhs, depth + 1));
+        }
+        ExprType::Address(name) => { return format!("{}Address {}\n", indent, name); }
+        ExprType::Argument(index) => { return format!("{}Argument {}\n", indent, index); }
+        ExprType::ToString(x) => { return format!("{}ToString\n{}", indent, tree_expr(x, depth + 1)); }
+        ExprType::ToInt(x) => { return format!("{}ToInt\n{}", indent, tree_expr(x, depth + 1)); }
+        ExprType::Not(x) => { return format!("{}Not\n{}", indent, tree_expr(x, depth + 1)); }
+        ExprType::Neg(x) => { return format!("{}Neg\n{}", indent, tree_expr(x, depth + 1)); }
+    }
+}
+
+pub fn tree(stmts: &Vec<StmtType>, depth: usize) -> String {
+    let indent = "  ".repeat(depth);
+    let mut out = String::new();
+
+    for stmt in stmts.iter() {
+        match stmt {
+            StmtType::Print(exprs) => {
+                out += &format!("{}Print\n", indent);
+
+                for expr in exprs.iter() {
+                    out += &tree_expr(expr, depth + 1);
+                }
+            }
+            StmtType::Say(exprs) => {
+                out += &format!("{}Say\n", indent);
+
+                for expr in exprs.iter() {
+                    out += &tree_expr(expr, depth + 1);
+                }
+            }
+            StmtType::Write(exprs) => {
+                out += &format!("{}Write\n", indent);
+
+                for expr in exprs.iter() {
+                    out += &tree_expr(expr, depth + 1);
+                }
+            }
+            StmtType::RawPrint(expr) => {
+                out += &format!("{}RawPrint\n", indent);
+                out += &format!("{}  {}\n", indent, expr);
+            }
+            StmtType::Debug(name) => {
+                out += &format!("{}Debug\n", indent);
+                out += &format!("{}  {}\n", indent, name);
+            }
+            StmtType::Expect { condition } => {
+                out += &format!("{}Expect\n", indent);
+                out += &tree_expr(condition, depth + 1);
+            }
+            StmtType::Let { key, value, .. } => {
+                out += &format!("{}Let\n", indent);
+                out += &format!("{}  key: {}\n", indent, tree_token(key));
+                out += &format!("{}  value:\n", indent);
+                out += &tree_expr(value, depth + 2);
+            }
+            StmtType::Assign { key, value } => {
+                out += &format!("{}Assign\n", indent);
+                out += &format!("{}  key: {}\n", indent, key);
+                out += &format!("{}  value:\n", indent);
+                out += &tree_expr(value, depth + 2);
+            }
+            StmtType::Freeze => {
+                out += &format!("{}Freeze\n", indent);
+            }
+            StmtType::Here(line) => {
+                out += &format!("{}Here\n", indent);
+                out += &format!("{}  line {}\n", indent, line);
+            }
+            StmtType::If { condition, then_body, else_body } => {
+                out += &format!("{}If\n", indent);
+                out += &format!("{}  condition:\n", indent);
+                out += &tree_expr(condition, depth + 2);
+                out += &format!("{}  then:\n", indent);
+                out += &tree(then_body, depth + 2);
+
+                if !else_body.is_empty() {
+                    out += &format!("{}  else:\n", indent);
+                    out += &tree(else_body, depth + 2);
+                }
+            }
+            StmtType::While { condition, body } => {
+                out += &format!("{}While\n", indent);
+                out += &format!("{}  condition:\n", indent);
+                out += &tree_expr(condition, depth + 2);
+                out += &format!("{}  body:\n", indent);
+                out += &tree(body, depth + 2);
+            }
+        }
+    }
+
+    return out;
+}
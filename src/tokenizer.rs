@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone)]
+#[allow(dead_code)]
+pub enum UserType {
+    // quoted literal, `"hello"`
+    String(String),
+    // bare word naming a variable, `hello`
+    Identifier(String),
+    // an i32 once the parser has folded in any unary minus, until then up to 2147483648 for `-2147483648`
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TokenType {
+    UserType(UserType),
+    Print,
+    Debug,
+    Let,
+    To,
+    OpenParen,
+    CloseParen,
+    Comma,
+    EqualEqual,
+    // a lone `=`, only there to be reported, Zynk assigns with `to`
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    And,
+    Or,
+    Not,
+    Expect,
+    Freeze,
+    // carries the source line it appeared on
+    Here(usize),
+    Address,
+    Args,
+    ToString,
+    ToInt,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    CheckType,
+    Is,
+    If,
+    Else,
+    End,
+    While,
+    Set,
+    RawPrint,
+    Say,
+    Write,
+}
+
+#[derive(Debug)]
+pub struct Token {
+    pub kind: TokenType,
+    // 1-based position of the token's first character
+    pub line: usize,
+    pub col: usize,
+}
+
+pub enum TokenizerError {
+    Ok,
+    Err(String)
+}
+
+// longer identifiers are rejected, nothing legitimate comes close
+const MAX_IDENTIFIER_LENGTH: usize = 1024;
+
+pub const KEYWORDS: &[&str] = &["print", "say", "write", "raw_print", "debug", "expect", "freeze", "here", "address", "args", "to_string", "to_int", "check_type", "is", "if", "else", "end", "while", "set", "let", "to", "true", "false", "and", "or", "not"];
+
+pub struct Tokenizer <'a> {
+    content: &'a String,
+    // `content` by character, so multi-byte UTF-8 can be indexed safely
+    chars: Vec<char>,
+    pub tokens: Vec<Token>,
+    // line and column the token being scanned starts at
+    start: (usize, usize),
+    // char range of every token in `chars`
+    pub spans: Vec<(usize, usize)>,
+    index: usize,
+    line: usize,
+    // 1-based column of `index`, tracked like `line` so it never has to be counted back
+    col: usize,
+    // alias -> built-in keyword, e.g. "imprimir" -> "print"
+    pub keywords: HashMap<String, String>,
+    // report errors and skip to the next whitespace instead of stopping
+    pub keep_going: bool,
+    // line, column and message of every error, without `keep_going` there's only the first
+    pub errors: Vec<(usize, usize, String)>,
+    pub warnings: Vec<String>,
+    // char index in `chars`, line and text (without the delimiters) of every comment
+    pub comments: Vec<(usize, usize, String)>,
+}
+
+impl Tokenizer <'_> {
+    pub fn new(content: &String) -> Tokenizer<'_> {
+        return Tokenizer {
+            content,
+            chars: content.chars().collect(),
+            tokens: Vec::new(),
+            start: (1, 1),
+            spans: Vec::new(),
+            index: 0,
+            line: 1,
+            col: 1,
+            keywords: HashMap::new(),
+            keep_going: false,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            comments: Vec::new(),
+        }
+    }
+
+    pub fn run(self: &mut Self) -> TokenizerError {
+        let buffer = &mut String::new();
+
+        self.check_indentation();
+
+        while self.peek(None).is_some() {
+            let start = self.index;
+            let token_count = self.tokens.len();
+            self.start = (self.line, self.col);
+
+            if self.peek(None).unwrap().is_alphabetic() || self.peek(None).unwrap() == '_' {
+                self.accept_to_buffer(buffer);
+                let mut length = 1;
+
+                while self.peek(None).is_some() && (self.peek(None).unwrap().is_alphanumeric() || self.peek(None).unwrap() == '_') {
+                    // past the cap the rest is skipped instead of buffered
+                    if length < MAX_IDENTIFIER_LENGTH {
+                        self.accept_to_buffer(buffer);
+                    } else {
+                        self.accept();
+                    }
+
+                    length += 1;
+                }
+
+                if length > MAX_IDENTIFIER_LENGTH {
+                    let err = format!("identifier too long, {} characters but at most {} are allowed", length, MAX_IDENTIFIER_LENGTH);
+                    buffer.clear();
+
+                    self.recover(err);
+
+                    if !self.keep_going {
+                        break;
+                    }
+
+                    continue;
+                }
+
+                if let Some(keyword) = self.keywords.get(buffer.as_str()) {
+                    *buffer = keyword.clone();
+                }
+
+                if buffer == "print" {
+                    self.push(TokenType::Print);
+                } else if buffer == "say" {
+                    self.push(TokenType::Say);
+                } else if buffer == "write" {
+                    self.push(TokenType::Write);
+                } else if buffer == "raw_print" {
+                    self.push(TokenType::RawPrint);
+                } else if buffer == "debug" {
+                    self.push(TokenType::Debug);
+                } else if buffer == "expect" {
+                    self.push(TokenType::Expect);
+                } else if buffer == "freeze" {
+                    self.push(TokenType::Freeze);
+                } else if buffer == "here" {
+                    self.push(TokenType::Here(self.line));
+                } else if buffer == "address" {
+                    self.push(TokenType::Address);
+                } else if buffer == "args" {
+                    self.push(TokenType::Args);
+                } else if buffer == "to_string" {
+                    self.push(TokenType::ToString);
+                } else if buffer == "to_int" {
+                    self.push(TokenType::ToInt);
+                } else if buffer == "check_type" {
+                    self.push(TokenType::CheckType);
+                } else if buffer == "is" {
+                    self.push(TokenType::Is);
+                } else if buffer == "if" {
+                    self.push(TokenType::If);
+                } else if buffer == "else" {
+                    self.push(TokenType::Else);
+                } else if buffer == "end" {
+                    self.push(TokenType::End);
+                } else if buffer == "while" {
+                    self.push(TokenType::While);
+                } else if buffer == "set" {
+                    self.push(TokenType::Set);
+                } else if buffer == "and" {
+                    self.push(TokenType::And);
+                } else if buffer == "or" {
+                    self.push(TokenType::Or);
+                } else if buffer == "not" {
+                    self.push(TokenType::Not);
+                } else if buffer == "true" || buffer == "false" {
+                    self.push(TokenType::UserType(UserType::Bool(buffer == "true")));
+                } else if buffer == "let" {
+                    self.push(TokenType::Let);
+                } else if buffer == "to" {
+                    self.push(TokenType::To);
+                } else {
+                    self.push(TokenType::UserType(UserType::Identifier(buffer.to_string())))
+                }
+
+                buffer.clear();
+            } else if self.peek(None).unwrap().is_numeric() || (self.peek(None).unwrap() == '.' && self.peek(Some(1)).is_some() && self.peek(Some(1)).unwrap().is_numeric()) {
+                let mut fraction = self.peek(None).unwrap() == '.';
+                self.accept_to_buffer(buffer);
+
+                while self.peek(None).is_some() && self.peek(None).unwrap().is_numeric() {
+                    self.accept_to_buffer(buffer);
+                }
+
+                // `3.14`, a leading dot (`.5`) is fine but a trailing one (`5.`) is rejected below
+                if !fraction && self.peek(None) == Some('.') && self.peek(Some(1)).is_some() && self.peek(Some(1)).unwrap().is_numeric() {
+                    fraction = true;
+                    self.accept_to_buffer(buffer);
+
+                    while self.peek(None).is_some() && self.peek(None).unwrap().is_numeric() {
+                        self.accept_to_buffer(buffer);
+                    }
+                }
+
+                // `1e9`, `2.5E-3`, an exponent makes it a float too
+                let sign = self.peek(Some(1)) == Some('+') || self.peek(Some(1)) == Some('-');
+                let digit = if sign { 2 } else { 1 };
+                let exponent = matches!(self.peek(None), Some('e' | 'E')) && self.peek(Some(digit)).is_some() && self.peek(Some(digit)).unwrap().is_numeric();
+
+                if exponent {
+                    for _ in 0..digit {
+                        self.accept_to_buffer(buffer);
+                    }
+
+                    while self.peek(None).is_some() && self.peek(None).unwrap().is_numeric() {
+                        self.accept_to_buffer(buffer);
+                    }
+                }
+
+                // a trailing `f` forces a float, `5f` is the float 5
+                let suffix_f = self.peek(None).is_some() && (self.peek(None).unwrap() == 'f' || self.peek(None).unwrap() == 'F');
+                let suffix_f = suffix_f && !(self.peek(Some(1)).is_some() && self.peek(Some(1)).unwrap().is_alphanumeric());
+
+                if suffix_f {
+                    self.accept();
+                }
+
+                let float = fraction || exponent || suffix_f;
+
+                // anything glued to the literal (`5x`, `3.14abc`, `5.`, `1.2.3`) is a typo, not two tokens
+                let mut offset = 0;
+
+                while self.peek(Some(offset)).is_some() && (self.peek(Some(offset)).unwrap().is_alphanumeric() || self.peek(Some(offset)).unwrap() == '.') {
+                    offset += 1;
+                }
+
+                let user_type = if offset > 0 {
+                    if suffix_f {
+                        buffer.push('f');
+                    }
+
+                    for _ in 0..offset {
+                        self.accept_to_buffer(buffer);
+                    }
+
+                    Err(format!("invalid number literal '{}'", buffer))
+                } else if float {
+                    match buffer.parse::<f64>() {
+                        Ok(x) if x.is_finite() => Ok(UserType::Float(x)),
+                        _ => Err(format!("float literal '{}' out of range", buffer)),
+                    }
+                } else {
+                    match buffer.parse::<i64>() {
+                        Ok(x) if x <= -(i32::MIN as i64) => Ok(UserType::Int(x)),
+                        _ => Err(format!("integer literal {} does not fit in i32 (at most {})", buffer, i32::MAX)),
+                    }
+                };
+
+                buffer.clear();
+
+                match user_type {
+                    Ok(user_type) => { self.push(TokenType::UserType(user_type)); }
+                    Err(err) => {
+                        self.recover(err);
+
+                        if !self.keep_going {
+                            break;
+                        }
+
+                        continue;
+                    }
+                }
+            } else if self.peek(None).unwrap() == '(' {
+                self.accept();
+
+                self.push(TokenType::OpenParen);
+            } else if self.peek(None).unwrap() == ')' {
+                self.accept();
+
+                self.push(TokenType::CloseParen);
+            } else if self.peek(None).unwrap() == ',' {
+                self.accept();
+
+                self.push(TokenType::Comma);
+            } else if self.peek(None).unwrap() == '"' {
+                self.accept();
+                // reported once the whole literal is consumed, so its rest isn't tokenized as code
+                let mut escape_error = None;
+
+                while self.peek(None).is_some() && self.peek(None).unwrap() != '"' {
+                    if self.peek(None).unwrap() == '\\' && self.peek(Some(1)).is_some() {
+                        self.accept();
+
+                        match self.accept() {
+                            Some('n') => { buffer.push('\n'); }
+                            Some('t') => { buffer.push('\t'); }
+                            Some('r') => { buffer.push('\r'); }
+                            Some(char @ ('"' | '\\')) => { buffer.push(char); }
+                            Some(char) => { escape_error = escape_error.or(Some(format!("unknown escape '\\{}' in string literal", char))); }
+                            None => {}
+                        }
+                    } else {
+                        self.accept_to_buffer(buffer);
+                    }
+                }
+
+                if self.peek(None).is_none() {
+                    let err = String::from("unterminated string literal");
+                    buffer.clear();
+
+                    self.recover(err);
+
+                    if !self.keep_going {
+                        break;
+                    }
+
+                    continue;
+                }
+
+                // closing quote
+                self.accept();
+
+                if let Some(err) = escape_error {
+                    let (line, col) = self.start;
+                    self.errors.push((line, col, err));
+                    buffer.clear();
+
+                    if !self.keep_going {
+                        break;
+                    }
+
+                    continue;
+                }
+
+                self.push(TokenType::UserType(UserType::String(buffer.to_string())));
+                buffer.clear();
+            } else if self.peek(None).unwrap() == '+' {
+                self.accept();
+
+                self.push(TokenType::Plus);
+            } else if self.peek(None).unwrap() == '-' {
+                self.accept();
+
+                self.push(TokenType::Minus);
+            } else if self.peek(None).unwrap() == '*' {
+                self.accept();
+
+                self.push(TokenType::Star);
+            } else if self.peek(None).unwrap() == '/' && self.peek(Some(1)) == Some('/') {
+                // line comment, the newline itself is left for the next iteration
+                let start = self.index;
+
+                while self.peek(None).is_some() && self.peek(None).unwrap() != '\n' {
+                    self.accept();
+                }
+
+                self.comments.push((start, self.line, self.chars[start + 2..self.index].iter().collect()));
+            } else if self.peek(None).unwrap() == '/' && self.peek(Some(1)) == Some('*') {
+                // block comments don't nest, the first `*/` closes the comment no matter
+                // how many `/*` came before it
+                let (start, line) = (self.index, self.line);
+                self.accept();
+                self.accept();
+
+                while self.peek(None).is_some() && !(self.peek(None).unwrap() == '*' && self.peek(Some(1)) == Some('/')) {
+                    self.accept();
+                }
+
+                if self.peek(None).is_none() {
+                    let err = String::from("unterminated block comment");
+
+                    self.recover(err);
+
+                    if !self.keep_going {
+                        break;
+                    }
+
+                    continue;
+                }
+
+                self.comments.push((start, line, self.chars[start + 2..self.index].iter().collect()));
+                self.accept();
+                self.accept();
+            } else if self.peek(None).unwrap() == '/' {
+                self.accept();
+
+                self.push(TokenType::Slash);
+            } else if self.peek(None).unwrap() == '=' && self.peek(Some(1)) == Some('=') {
+                self.accept();
+                self.accept();
+
+                self.push(TokenType::EqualEqual);
+            } else if self.peek(None).unwrap() == '=' {
+                self.accept();
+
+                self.push(TokenType::Equal);
+            } else if self.peek(None).unwrap() == '!' && self.peek(Some(1)) == Some('=') {
+                self.accept();
+                self.accept();
+
+                self.push(TokenType::NotEqual);
+            } else if self.peek(None).unwrap() == '<' || self.peek(None).unwrap() == '>' {
+                // `<=` and `>=` are checked first so they don't split into two tokens
+                let less = self.accept() == Some('<');
+
+                if self.peek(None) == Some('=') {
+                    self.accept();
+
+                    self.push(if less { TokenType::LessEqual } else { TokenType::GreaterEqual });
+                } else {
+                    self.push(if less { TokenType::Less } else { TokenType::Greater });
+                }
+            } else if self.peek(None).unwrap().is_whitespace() {
+                self.accept();
+            } else {
+                let err = format!("unexpected character '{}'", self.peek(None).unwrap());
+
+                self.recover(err);
+
+                if !self.keep_going {
+                    break;
+                }
+
+                continue;
+            }
+
+            if self.tokens.len() > token_count {
+                self.spans.push((start, self.index));
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return TokenizerError::Err(format!("{} errors while tokenizing", self.errors.len()));
+        }
+
+        return TokenizerError::Ok;
+    }
+
+    /// Warns once if leading whitespace uses tabs on some lines and spaces on others.
+    fn check_indentation(self: &mut Self) {
+        let mut first_tab = None;
+        let mut first_space = None;
+
+        for (number, line) in self.content.lines().enumerate() {
+            for char in line.chars().take_while(|char| *char == ' ' || *char == '\t') {
+                if char == '\t' {
+                    first_tab = first_tab.or(Some(number + 1));
+                } else {
+                    first_space = first_space.or(Some(number + 1));
+                }
+            }
+        }
+
+        if let (Some(tab), Some(space)) = (first_tab, first_space) {
+            self.warnings.push(format!("indentation mixes tabs (line {}) and spaces (line {})", tab, space));
+        }
+    }
+
+    fn recover(self: &mut Self, err: String) {
+        let (line, col) = self.start;
+        self.errors.push((line, col, err));
+
+        while self.peek(None).is_some() && !self.peek(None).unwrap().is_whitespace() {
+            self.accept();
+        }
+    }
+
+    fn peek(self: &Self, _offset: Option<usize>) -> Option<char> {
+        let offset = _offset.unwrap_or(0);
+
+        return self.chars.get(self.index + offset).copied();
+    }
+
+    fn accept(self: &mut Self) -> Option<char> {
+        self.index += 1;
+        let char = self.chars.get(self.index - 1).copied();
+
+        if char == Some('\n') {
+            self.line += 1;
+            self.col = 1;
+        } else if char.is_some() {
+            self.col += 1;
+        }
+
+        return char;
+    }
+
+    fn push(self: &mut Self, kind: TokenType) {
+        let (line, col) = self.start;
+        self.tokens.push(Token { kind, line, col });
+    }
+
+    fn accept_to_buffer(self: &mut Self, buffer: &mut String) {
+        if let Some(char) = self.accept() {
+            buffer.push(char);
+        }
+    }
+}
+
+/// Parses `alias = keyword` lines, blank lines and surrounding whitespace are ignored.
+pub fn parse_keyword_map(content: &str) -> Result<HashMap<String, String>, String> {
+    let mut keywords = HashMap::new();
+
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let Some((alias, keyword)) = line.split_once('=') else {
+            return Err(format!("keyword map line {}: expected 'alias = keyword'", number + 1));
+        };
+        let (alias, keyword) = (alias.trim(), keyword.trim());
+
+        if !KEYWORDS.contains(&keyword) {
+            return Err(format!("keyword map line {}: '{}' is not a keyword", number + 1, keyword));
+        }
+
+        keywords.insert(String::from(alias), String::from(keyword));
+    }
+
+    return Ok(keywords);
+}